@@ -0,0 +1,582 @@
+//! Command line use of the calculator, which drives the same `App` as the GUI
+
+use std::path::Path;
+
+use iced::Sandbox;
+
+use crate::{
+    export,
+    model::{self, EnvelopeModel, Values},
+    rom, App, Message,
+};
+
+const USAGE: &str = "\
+Usage: adsr_calculator <command> [arguments...]
+
+list
+    Every model with its console, tick rate and parameters.
+
+<model> <attack> <decay> <sustain> <release> [hold] [parameters...]
+    Integers within the model's range are converted to SF2 times, anything
+    else is converted to the model's own values, the same as in the GUI.
+
+port <from model> <to model> <attack> <decay> <sustain> <release> [hold]
+    Takes the values of one model and finds the closest ones on another.
+
+all <model> <attack> <decay> <sustain> <release> [hold] [parameters...]
+    Ports the values to every model at once.
+
+midi <GM program 1-128> <model> <attack> <decay> <sustain> <release> [hold]
+    The GS/XG NRPN and GM2 controller messages that bring a General MIDI
+    instrument's envelope closest to the given one.
+
+sfz <model> <attack> <decay> <sustain> <release> [hold] [parameters...]
+    The SFZ opcodes for an envelope.
+
+bank <model> <instruments file> <output file> [decomp folder]
+    Reads a file with an instrument per line, the envelope being the last four
+    numbers and the sample the word before them (as in a voicegroup.inc file),
+    and writes an instrument for each in the format of the output's extension:
+    .sfz, .sf2, .dls, .dspreset or .xml.
+    Nitro Studio 2 bank XML works as input for nds, and as output into a bank
+    saved from Nitro Studio 2, replacing only its envelopes. An SF2 takes its
+    samples from the decomp checkout folder if one is given.
+
+load <instrument .fui>
+    Reads a Furnace NDS or GBA DirectSound instrument's volume macro as an
+    envelope.
+
+rom <game .gba/.nds, .sdat or song .s>
+    Every MP2K voice in a GBA game, or every SBNK instrument in an NDS game's
+    SDATs, with its envelope, and the envelope overrides in their songs. A
+    decomp .s song file lists its XCMD overrides.
+
+sf2 <game .nds or .sdat> <bank> <output .sf2> [volume or sequence]
+    Writes an SBNK bank's instruments, by name or number, with a zone for
+    every key region and the samples from its SWARs. The last argument plays
+    the bank at an NDS volume (0-127), or at the volume of the sequence with
+    that name, as the zones' attenuation.
+
+sf2 <game .gba> <song number or voicegroup 0x address> <output .sf2>
+    Writes a song's voicegroup, or the voicegroup at an address, with
+    keysplits and drums as key ranges.
+
+volume <NDS volume 0-127> [NDS pan 0-127]
+    An NDS volume and pan in SF2 units.
+
+lfo nds <depth> [speed] [type] [range] [delay]
+lfo gba <MOD> [LFOS] [MODT] [LFODL]
+    SSEQ or MP2K modulation as SF2's vibrato or modulation LFO, with type or
+    MODT 0 for pitch, 1 for volume and 2 for pan.";
+
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut app = App::new();
+    match args[0].as_str() {
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        "list" => {
+            for model in &app.models {
+                let mut line = format!("{}\t{}", model.id(), model.console());
+                if model.name() != model.console() {
+                    line += &format!(" {}", model.name());
+                }
+                line += &format!(", {:.3} ticks/s", model.ticks_per_second());
+                for parameter in model.parameters() {
+                    line += &format!(" [{}]", parameter.name);
+                }
+                println!("{}", line);
+            }
+            return Ok(());
+        }
+        "port" => {
+            if args.len() < 7 {
+                return Err(USAGE.to_string());
+            }
+            let target = find_model(&app, &args[2])?;
+            enter(&mut app, &args[1], &args[3..])?;
+            if app.putting_in {
+                return Err("Porting needs the console's own values".to_string());
+            }
+            app.port(target);
+            println!("{}", app.result);
+            return Ok(());
+        }
+        "all" => {
+            if args.len() < 6 {
+                return Err(USAGE.to_string());
+            }
+            enter(&mut app, &args[1], &args[2..])?;
+            if app.putting_in {
+                return Err("Porting needs the console's own values".to_string());
+            }
+            app.show_matrix();
+            println!("{}", app.result);
+            return Ok(());
+        }
+        "midi" => {
+            if args.len() < 7 {
+                return Err(USAGE.to_string());
+            }
+            app.update(Message::ProgramChanged(args[1].clone()));
+            enter(&mut app, &args[2], &args[3..])?;
+            if app.putting_in {
+                return Err("MIDI needs the console's own values".to_string());
+            }
+            app.show_midi();
+            println!("{}", app.result);
+            return Ok(());
+        }
+        "sfz" => {
+            if args.len() < 6 {
+                return Err(USAGE.to_string());
+            }
+            enter(&mut app, &args[1], &args[2..])?;
+            if app.putting_in {
+                return Err("SFZ needs the console's own values".to_string());
+            }
+            app.show_sfz();
+            println!("{}", app.result);
+            return Ok(());
+        }
+        "load" => {
+            if args.len() < 2 {
+                return Err(USAGE.to_string());
+            }
+            app.file_path = args[1].clone();
+            let result = app.load()?;
+            println!(
+                "{} {}",
+                app.model().id(),
+                app.model().format_values(&app.values())
+            );
+            println!("{}", result);
+            return Ok(());
+        }
+        "rom" => {
+            if args.len() < 2 {
+                return Err(USAGE.to_string());
+            }
+            let data = std::fs::read(&args[1]).map_err(|e| e.to_string())?;
+            let extension = Path::new(&args[1])
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase());
+            match extension.as_deref() {
+                Some("nds") | Some("sdat") => print_sdat_instruments(&app, &data)?,
+                Some("s") => print_assembly_overrides(&app, &String::from_utf8_lossy(&data))?,
+                _ => print_gba_voices(&app, &data)?,
+            }
+            return Ok(());
+        }
+        "sf2" => {
+            if args.len() < 4 {
+                return Err(USAGE.to_string());
+            }
+            let data = std::fs::read(&args[1]).map_err(|e| e.to_string())?;
+            let extension = Path::new(&args[1])
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase());
+            let (name, presets, samples) = match extension.as_deref() {
+                Some("nds") | Some("sdat") => {
+                    sdat_sf2(&app, &data, &args[2], args.get(4).map(String::as_str))?
+                }
+                _ => voicegroup_sf2(&app, &data, &args[2])?,
+            };
+            let sf2 = export::sf2::write(&name, &presets, &samples)?;
+            std::fs::write(&args[3], sf2).map_err(|e| e.to_string())?;
+            println!(
+                "Wrote {} instruments and {} samples to {}",
+                presets.len(),
+                samples.len(),
+                args[3]
+            );
+            return Ok(());
+        }
+        "lfo" => {
+            if args.len() < 3 {
+                return Err(USAGE.to_string());
+            }
+            app.select_model(find_model(&app, &args[1])?);
+            for (i, value) in args[2..].iter().enumerate().take(app.lfo_inputs.len()) {
+                app.update(Message::LfoChanged(i, value.clone()));
+            }
+            println!("{}", app.lfo()?);
+            return Ok(());
+        }
+        "volume" => {
+            let volume = args
+                .get(1)
+                .and_then(|volume| volume.parse::<u8>().ok())
+                .ok_or(USAGE.to_string())?
+                .min(127);
+            let attenuation = model::nds_attenuation(volume);
+            println!(
+                "Volume {}: initialAttenuation {:.0} cB ({:.1} dB)",
+                volume,
+                attenuation,
+                attenuation / 10.0
+            );
+            if let Some(pan) = args.get(2).and_then(|pan| pan.parse::<u8>().ok()) {
+                let pan = pan.min(127);
+                println!("Pan {}: pan {}", pan, model::nds_pan(pan));
+            }
+            return Ok(());
+        }
+        "bank" => {
+            if args.len() < 4 {
+                return Err(USAGE.to_string());
+            }
+            let model = app.models[find_model(&app, &args[1])?].as_ref();
+            let instruments = export::read_bank(model, Path::new(&args[2]))?;
+            if instruments.is_empty() {
+                return Err(format!("No envelopes found in {}", args[2]));
+            }
+            let decomp = args.get(4).map(Path::new);
+            export::save(model, &instruments, Path::new(&args[3]), 0, decomp)?;
+            println!("Wrote {} instruments to {}", instruments.len(), args[3]);
+            return Ok(());
+        }
+        _ => {}
+    }
+    if args.len() < 5 {
+        return Err(USAGE.to_string());
+    }
+    enter(&mut app, &args[0], &args[1..])?;
+    app.update(Message::CalculatePressed);
+    println!("{}", app.result);
+    Ok(())
+}
+
+type Sf2Bank = (String, Vec<export::sf2::Preset>, Vec<export::sf2::Sample>);
+
+/// An SBNK bank by name or number, at a volume or a sequence's volume
+fn sdat_sf2(app: &App, data: &[u8], bank: &str, volume: Option<&str>) -> Result<Sf2Bank, String> {
+    let archives = match rom::sdat::read("SDAT".to_string(), data) {
+        Some(sdat) => vec![sdat],
+        None => rom::sdat::find(data),
+    };
+    let (sdat, bank) = archives
+        .iter()
+        .flat_map(|sdat| sdat.banks.iter().map(move |b| (sdat, b)))
+        .find(|(_, b)| b.name == bank || b.id.to_string() == bank)
+        .ok_or_else(|| format!("No bank called {}, see rom", bank))?;
+    let volume = match volume {
+        None => 127,
+        Some(volume) => match volume.parse::<u8>() {
+            Ok(volume) => volume.min(127),
+            Err(_) => sdat
+                .sequences
+                .iter()
+                .find(|sequence| sequence.name == volume)
+                .map(|sequence| sequence.volume)
+                .ok_or_else(|| format!("No sequence called {}", volume))?,
+        },
+    };
+    let model = app.models[find_model(app, "nds")?].as_ref();
+    let (presets, samples) = export::sf2::sbnk(model, sdat, bank, volume);
+    Ok((bank.name.clone(), presets, samples))
+}
+
+/// A song's voicegroup, or the voicegroup at a 0x address
+fn voicegroup_sf2(app: &App, data: &[u8], which: &str) -> Result<Sf2Bank, String> {
+    let scan = rom::gba::scan(data)?;
+    let address = match which.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).map_err(|e| e.to_string())?,
+        None => {
+            let song = which.parse::<usize>().map_err(|e| e.to_string())?;
+            let header = scan
+                .songs
+                .get(song)
+                .ok_or_else(|| format!("There are only {} songs", scan.songs.len()))?;
+            rom::gba::song_voicegroup(data, *header).unwrap_or(0)
+        }
+    };
+    if !scan.voicegroups.contains_key(&address) {
+        return Err(format!("No voicegroup at 0x{:08X}, see rom", address));
+    }
+    let direct = app.models[find_model(app, "gba")?].as_ref();
+    let cgb = app.models[find_model(app, "cgb")?].as_ref();
+    let (presets, samples) = export::sf2::voicegroup(direct, cgb, data, &scan, address);
+    Ok((format!("Voicegroup 0x{:08X}", address), presets, samples))
+}
+
+fn print_gba_voices(app: &App, data: &[u8]) -> Result<(), String> {
+    let scan = rom::gba::scan(data)?;
+    println!(
+        "Song table at 0x{:08X}, {} songs, {} voicegroups",
+        scan.song_table,
+        scan.songs.len(),
+        scan.voicegroups.len()
+    );
+    for voicegroup in scan.voicegroups.values() {
+        println!("\nVoicegroup 0x{:08X}", voicegroup.address);
+        for (program, voice) in &voicegroup.voices {
+            let tone = match voice {
+                rom::gba::Voice::Tone(tone) => tone,
+                rom::gba::Voice::Keysplit { voicegroup, .. } => {
+                    println!("{:>3}: Keysplit 0x{:08X}", program, voicegroup);
+                    continue;
+                }
+                rom::gba::Voice::Drums { voicegroup } => {
+                    println!("{:>3}: Drums 0x{:08X}", program, voicegroup);
+                    continue;
+                }
+            };
+            let model = app.models[find_model(app, tone.kind.model_id())?].as_ref();
+            let times = model.to_times(&tone.values);
+            let data = match tone.kind {
+                rom::gba::ToneKind::DirectSound | rom::gba::ToneKind::Wave => {
+                    format!("0x{:08X}", tone.data)
+                }
+                _ => tone.data.to_string(),
+            };
+            let fixed = if tone.fixed { " fixed" } else { "" };
+            println!(
+                "{:>3}: {:<11} {}{} key {} pan {}, {:<18} A {:.3} D {:.3} S {:.3} R {:.3}",
+                program,
+                tone.kind.name(),
+                data,
+                fixed,
+                tone.key,
+                tone.pan,
+                model.format_values(&tone.values),
+                times.attack,
+                times.decay,
+                times.sustain,
+                times.release
+            );
+        }
+    }
+    for (song, &header) in scan.songs.iter().enumerate() {
+        let overrides = rom::song::overrides(data, header).unwrap_or_default();
+        if !overrides.is_empty() {
+            println!("\nSong {} (header 0x{:08X})", song, header);
+            let voicegroup = rom::gba::song_voicegroup(data, header).unwrap_or(0);
+            for entry in &overrides {
+                let tone = scan.tone(data, voicegroup, entry.program, entry.key.unwrap_or(60));
+                print_song_override(app, entry, tone)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Each override next to the value the voicegroup would have used
+fn print_song_override(
+    app: &App,
+    entry: &rom::song::Override,
+    tone: Option<&rom::gba::Tone>,
+) -> Result<(), String> {
+    let stage = entry.stage;
+    let kind = tone.map_or(rom::gba::ToneKind::DirectSound, |tone| tone.kind);
+    let model = app.models[find_model(app, kind.model_id())?].as_ref();
+    // Songs without a voicegroup are taken as a DirectSound voice left at full volume
+    let mut values = tone.map_or(
+        Values {
+            attack: 255,
+            sustain: 255,
+            ..Values::default()
+        },
+        |tone| tone.values.clone(),
+    );
+    let voice_value = stage.value(&values);
+    let voice_time = stage.time(&model.to_times(&values));
+    stage.set(&mut values, entry.value);
+    let mut line = format!(
+        "Track {} tick {}, program {}: {} {} ({:.3})",
+        entry.track,
+        entry.tick,
+        entry.program,
+        stage.name(),
+        entry.value,
+        stage.time(&model.to_times(&values))
+    );
+    if tone.is_some() {
+        line += &format!(", voice {} ({:.3})", voice_value, voice_time);
+        if entry.value != voice_value {
+            line += " differs";
+        }
+    }
+    println!("{}", line);
+    Ok(())
+}
+
+fn print_assembly_overrides(app: &App, text: &str) -> Result<(), String> {
+    let overrides = rom::song::assembly_overrides(text);
+    if overrides.is_empty() {
+        return Err("No XCMD envelope overrides in the song".to_string());
+    }
+    for entry in &overrides {
+        print_song_override(app, entry, None)?;
+    }
+    Ok(())
+}
+
+fn print_note(model: &dyn EnvelopeModel, label: &str, note: &rom::sdat::Note) {
+    let kind = match note.kind {
+        2 => format!("PSG duty {}", note.wave),
+        3 => "Noise".to_string(),
+        _ => format!("Wave {}/{}", note.archive, note.wave),
+    };
+    let times = model.to_times(&note.values);
+    println!(
+        "{}: {} key {} pan {}, {} A {:.3} D {:.3} S {:.3} R {:.3}",
+        label,
+        kind,
+        note.root_key,
+        note.pan,
+        model.format_values(&note.values).replace('\t', ", "),
+        times.attack,
+        times.decay,
+        times.sustain,
+        times.release
+    );
+}
+
+fn print_sdat_instruments(app: &App, data: &[u8]) -> Result<(), String> {
+    let archives = match rom::sdat::read("SDAT".to_string(), data) {
+        Some(sdat) => vec![sdat],
+        None => rom::sdat::find(data),
+    };
+    if archives.is_empty() {
+        return Err("Couldn't find an SDAT".to_string());
+    }
+    let model = app.models[find_model(app, "nds")?].as_ref();
+    for sdat in archives {
+        println!(
+            "{} ({} bytes), {} banks",
+            sdat.path,
+            sdat.data.len(),
+            sdat.banks.len()
+        );
+        for bank in &sdat.banks {
+            println!("\n{}, wave archives {:?}", bank.name, bank.wave_archives);
+            for (program, instrument) in &bank.instruments {
+                match instrument {
+                    rom::sdat::Instrument::Single(note) => {
+                        print_note(model, &format!("{:>3}", program), note)
+                    }
+                    rom::sdat::Instrument::Drums { low, notes } => {
+                        for (key, note) in notes.iter().enumerate() {
+                            let label = format!("{:>3} key {}", program, *low as usize + key);
+                            print_note(model, &label, note);
+                        }
+                    }
+                    rom::sdat::Instrument::KeySplit(regions) => {
+                        for (high, note) in regions {
+                            print_note(model, &format!("{:>3} to key {}", program, high), note);
+                        }
+                    }
+                }
+            }
+        }
+        for sequence in &sdat.sequences {
+            if !sequence.overrides.is_empty() {
+                print_overrides(model, &sdat, sequence);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Each override next to the value the bank would have used
+fn print_overrides(
+    model: &dyn EnvelopeModel,
+    sdat: &rom::sdat::Sdat,
+    sequence: &rom::sdat::Sequence,
+) {
+    let bank = sdat.bank(sequence.bank);
+    println!(
+        "\n{} ({}), volume {} ({:.0} cB attenuation)",
+        sequence.name,
+        bank.map_or("missing bank".to_string(), |bank| bank.name.clone()),
+        sequence.volume,
+        model::nds_attenuation(sequence.volume)
+    );
+    for entry in &sequence.overrides {
+        let note = bank
+            .and_then(|bank| {
+                bank.instruments
+                    .iter()
+                    .find(|(program, _)| *program == entry.program)
+            })
+            .and_then(|(_, instrument)| match entry.key {
+                Some(key) => instrument.note(key),
+                None => instrument.first_note(),
+            });
+        let stage = entry.stage;
+        let mut line = format!(
+            "Track {} tick {}, program {}: {} ",
+            entry.track,
+            entry.tick,
+            entry.program,
+            stage.name()
+        );
+        let overridden = match (entry.value, note) {
+            (Some(255), _) => {
+                line += "back to the instrument's";
+                None
+            }
+            (None, _) => {
+                line += "random or from a variable";
+                None
+            }
+            (Some(value), Some(note)) => {
+                let mut values = note.values.clone();
+                stage.set(&mut values, value);
+                line += &format!("{} ({:.3})", value, stage.time(&model.to_times(&values)));
+                Some((value, note))
+            }
+            (Some(value), None) => {
+                line += &value.to_string();
+                None
+            }
+        };
+        if let Some((value, note)) = overridden {
+            let bank_value = stage.value(&note.values);
+            line += &format!(
+                ", bank {} ({:.3})",
+                bank_value,
+                stage.time(&model.to_times(&note.values))
+            );
+            if value != bank_value {
+                line += " differs";
+            }
+        }
+        println!("{}", line);
+    }
+}
+
+fn find_model(app: &App, id: &str) -> Result<usize, String> {
+    app.models
+        .iter()
+        .position(|model| model.id() == id)
+        .ok_or_else(|| format!("Unknown model \"{}\", see \"list\"", id))
+}
+
+/// Selects a model and types the values into its fields
+fn enter(app: &mut App, id: &str, values: &[String]) -> Result<(), String> {
+    let index = find_model(app, id)?;
+    app.select_model(index);
+
+    let mut values = values.iter().cloned();
+    let stages: [fn(String) -> Message; 4] = [
+        Message::AttackChanged,
+        Message::DecayChanged,
+        Message::SustainChanged,
+        Message::ReleaseChanged,
+    ];
+    for (stage, value) in stages.iter().zip(&mut values) {
+        app.update(stage(value));
+    }
+    if app.model().has_hold() {
+        app.update(Message::HoldChanged(
+            values.next().unwrap_or("0".to_string()),
+        ));
+    }
+    for (i, value) in values.enumerate().take(app.model().parameters().len()) {
+        app.update(Message::ParameterChanged(i, value));
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Decent Sampler presets (`.dspreset`), with the envelope on each instrument's group
+
+use crate::model::{EnvelopeModel, Times};
+
+use super::{escape, sfz::sustain_percent, Instrument};
+
+/// Stages that never end are written as this many seconds
+const MAX_TIME: f64 = 100.0;
+
+/// Decent Sampler's sustain goes from 0 to 1
+fn envelope_attributes(times: &Times) -> String {
+    format!(
+        "attack=\"{:.3}\" decay=\"{:.3}\" sustain=\"{:.3}\" release=\"{:.3}\"",
+        times.attack.min(MAX_TIME),
+        times.decay.min(MAX_TIME),
+        sustain_percent(times.sustain) / 100.0,
+        times.release.min(MAX_TIME)
+    )
+}
+
+pub fn preset(model: &dyn EnvelopeModel, instruments: &[Instrument]) -> String {
+    let mut preset = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_string();
+    preset += "<DecentSampler minVersion=\"1.0.0\">\n  <groups>\n";
+    for instrument in instruments {
+        let name = escape(&instrument.sample);
+        preset += &format!(
+            "    <group name=\"{}\" {}>\n",
+            name,
+            envelope_attributes(&model.to_times(&instrument.values))
+        );
+        preset += &format!(
+            "      <sample path=\"Samples/{}.wav\" rootNote=\"60\" loNote=\"0\" hiNote=\"127\"/>\n",
+            name
+        );
+        preset += "    </group>\n";
+    }
+    preset += "  </groups>\n</DecentSampler>\n";
+    preset
+}
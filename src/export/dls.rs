@@ -0,0 +1,161 @@
+//! Downloadable Sounds (DLS) Level 1 and 2 banks
+//!
+//! We only know the envelopes, so every instrument gets a single region playing a short silent
+//! placeholder wave named after its sample, to be replaced in a DLS editor. Banks with a hold
+//! stage are written as Level 2, everything else as Level 1.
+
+use crate::model::{EnvelopeModel, Times};
+
+use super::{sfz::sustain_percent, Instrument};
+
+const CONN_DST_EG1_ATTACKTIME: u16 = 0x0206;
+const CONN_DST_EG1_DECAYTIME: u16 = 0x0207;
+const CONN_DST_EG1_RELEASETIME: u16 = 0x0209;
+const CONN_DST_EG1_SUSTAINLEVEL: u16 = 0x020A;
+const CONN_DST_EG1_HOLDTIME: u16 = 0x020C;
+
+/// Longest attack, hold and release DLS allows, in seconds
+const MAX_TIME: f64 = 20.0;
+/// Longest decay DLS allows, in seconds
+const MAX_DECAY_TIME: f64 = 40.0;
+
+const SAMPLE_RATE: u32 = 22050;
+const PLACEHOLDER_SAMPLES: usize = 16;
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn list(kind: &[u8; 4], children: &[u8]) -> Vec<u8> {
+    let mut data = kind.to_vec();
+    data.extend(children);
+    chunk(b"LIST", &data)
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+/// Seconds as absolute timecents in 16.16 fixed point, 0 seconds being the lowest value
+fn timecents(seconds: f64, max: f64) -> i32 {
+    if seconds <= 0.0 {
+        i32::MIN
+    } else {
+        (1200.0 * seconds.min(max).log2() * 65536.0).round() as i32
+    }
+}
+
+/// Sustain in tenths of a percent, in 16.16 fixed point
+fn sustain_scale(decibels: f64) -> i32 {
+    (sustain_percent(decibels) * 10.0 * 65536.0).round() as i32
+}
+
+/// The envelope as connection blocks with no source, `art1` or `art2` depending on the level
+fn articulation(times: &Times, level_2: bool) -> Vec<u8> {
+    let mut connections = vec![
+        (CONN_DST_EG1_ATTACKTIME, timecents(times.attack, MAX_TIME)),
+        (
+            CONN_DST_EG1_DECAYTIME,
+            timecents(times.decay, MAX_DECAY_TIME),
+        ),
+        (CONN_DST_EG1_SUSTAINLEVEL, sustain_scale(times.sustain)),
+        (CONN_DST_EG1_RELEASETIME, timecents(times.release, MAX_TIME)),
+    ];
+    if level_2 {
+        connections.push((CONN_DST_EG1_HOLDTIME, timecents(times.hold, MAX_TIME)));
+    }
+    let mut data = words(&[8, connections.len() as u32]);
+    for (destination, scale) in connections {
+        // Source, control, destination, transform
+        for field in [0, 0, destination, 0] {
+            data.extend(u16::to_le_bytes(field));
+        }
+        data.extend(scale.to_le_bytes());
+    }
+    if level_2 {
+        list(b"lar2", &chunk(b"art2", &data))
+    } else {
+        list(b"lart", &chunk(b"art1", &data))
+    }
+}
+
+fn name(name: &str) -> Vec<u8> {
+    let mut text = name.as_bytes().to_vec();
+    text.push(0);
+    list(b"INFO", &chunk(b"INAM", &text))
+}
+
+fn instrument(program: u32, instrument: &Instrument, times: &Times, level_2: bool) -> Vec<u8> {
+    // Whole key and velocity range, no options or key group
+    let mut header = Vec::new();
+    for field in [0_u16, 127, 0, 127, 0, 0] {
+        header.extend(field.to_le_bytes());
+    }
+    // Unity note 60, no fine tune, attenuation, options or loops
+    let mut sample = words(&[20]);
+    for field in [60_u16, 0] {
+        sample.extend(field.to_le_bytes());
+    }
+    sample.extend(words(&[0, 0, 0]));
+    // No options or phase group, left channel, wave number
+    let mut link = words(&[0]);
+    link.extend(words(&[1, program]));
+
+    let mut region = chunk(b"rgnh", &header);
+    region.extend(chunk(b"wsmp", &sample));
+    region.extend(chunk(b"wlnk", &link));
+
+    let mut data = chunk(b"insh", &words(&[1, 0, program]));
+    data.extend(list(b"lrgn", &list(b"rgn ", &region)));
+    data.extend(articulation(times, level_2));
+    data.extend(name(&instrument.sample));
+    list(b"ins ", &data)
+}
+
+/// A silent 16-bit mono wave
+fn placeholder_wave(sample: &str) -> Vec<u8> {
+    let mut format = Vec::new();
+    for field in [1_u16, 1] {
+        format.extend(field.to_le_bytes());
+    }
+    format.extend(words(&[SAMPLE_RATE, SAMPLE_RATE * 2]));
+    for field in [2_u16, 16] {
+        format.extend(field.to_le_bytes());
+    }
+    let mut wave = chunk(b"fmt ", &format);
+    wave.extend(chunk(b"data", &[0; PLACEHOLDER_SAMPLES * 2]));
+    wave.extend(name(sample));
+    list(b"wave", &wave)
+}
+
+pub fn bank(model: &dyn EnvelopeModel, instruments: &[Instrument]) -> Vec<u8> {
+    let level_2 = model.has_hold();
+    let mut instrument_list = Vec::new();
+    let mut waves = Vec::new();
+    let mut offsets = Vec::new();
+    for (program, entry) in instruments.iter().enumerate() {
+        let times = model.to_times(&entry.values);
+        instrument_list.extend(instrument(program as u32, entry, &times, level_2));
+        offsets.push(waves.len() as u32);
+        waves.extend(placeholder_wave(&entry.sample));
+    }
+    let mut pool_table = words(&[8, offsets.len() as u32]);
+    pool_table.extend(words(&offsets));
+
+    let mut data = b"DLS ".to_vec();
+    data.extend(chunk(b"colh", &words(&[instruments.len() as u32])));
+    data.extend(list(b"lins", &instrument_list));
+    data.extend(chunk(b"ptbl", &pool_table));
+    data.extend(list(b"wvpl", &waves));
+    data.extend(name(model.name()));
+    chunk(b"RIFF", &data)
+}
@@ -0,0 +1,242 @@
+//! Furnace tracker instruments (`.fui`) for its NDS and GBA DirectSound chips
+//!
+//! Neither chip has a hardware envelope in Furnace, so the envelope is written as a volume macro
+//! that stops at its release point for the sustain. Stages follow SF2's curves, falling evenly in
+//! decibels, and a macro read back in is measured the same way.
+
+use crate::model::Times;
+
+/// Furnace's default tick rate
+const TICKS_PER_SECOND: f64 = 60.0;
+
+/// A Furnace version with both instrument types
+const VERSION: u16 = 228;
+
+const TYPE_NDS: u8 = 59;
+const TYPE_GBA_DMA: u8 = 60;
+
+const MACRO_VOLUME: u8 = 0;
+const MACRO_END: u8 = 255;
+const MACRO_HEADER_LENGTH: u16 = 8;
+const MAX_MACRO_LENGTH: usize = 255;
+/// No loop or release point
+const NONE: u8 = 255;
+
+/// Highest volume for each chip, and the model its envelopes are read into
+const CHIPS: [(u8, u8, &str); 2] = [(TYPE_NDS, 127, "nds"), (TYPE_GBA_DMA, 255, "gba")];
+
+/// Attenuation where the volume rounds down to 0
+fn silence(max_volume: u8) -> f64 {
+    20.0 * f64::log10(2.0 * max_volume as f64)
+}
+
+fn volume(decibels: f64, max_volume: u8) -> u8 {
+    (max_volume as f64 * 10_f64.powf(-decibels / 20.0)).round() as u8
+}
+
+fn decibels(volume: u8, max_volume: u8) -> f64 {
+    if volume == 0 {
+        silence(max_volume)
+    } else {
+        (20.0 * f64::log10(volume as f64 / max_volume as f64)).abs()
+    }
+}
+
+/// The envelope sampled every `speed` ticks, and the index of its sustain
+fn volume_macro(times: &Times, max_volume: u8, speed: u32) -> (Vec<u8>, usize) {
+    let tick = speed as f64 / TICKS_PER_SECOND;
+    let silence = silence(max_volume);
+    // A decay that never ends never gets down to the sustain
+    let sustain = if times.decay.is_finite() {
+        times.sustain.min(silence)
+    } else {
+        0.0
+    };
+    let mut steps = Vec::new();
+    let attack_ticks = (times.attack / tick).round() as u32;
+    for i in 0..attack_ticks.min(MAX_MACRO_LENGTH as u32 + 1) {
+        steps.push((max_volume as f64 * i as f64 / attack_ticks as f64).round() as u8);
+    }
+    // SF2 decay and release times are for the full 144 dB
+    let decay_rate = 144.0 / times.decay * tick;
+    let mut level = 0.0;
+    while level < sustain && steps.len() <= MAX_MACRO_LENGTH {
+        steps.push(volume(level, max_volume));
+        level += decay_rate;
+    }
+    steps.push(volume(sustain, max_volume));
+    let release_point = steps.len() - 1;
+    let release_rate = 144.0 / times.release * tick;
+    let mut level = sustain;
+    while times.release.is_finite() && level < silence && steps.len() <= MAX_MACRO_LENGTH {
+        level += release_rate;
+        steps.push(volume(level, max_volume));
+    }
+    (steps, release_point)
+}
+
+fn feature(code: &[u8; 2], data: &[u8]) -> Vec<u8> {
+    let mut feature = code.to_vec();
+    feature.extend((data.len() as u16).to_le_bytes());
+    feature.extend(data);
+    feature
+}
+
+/// An instrument for the model's console with its envelope as the volume macro
+pub fn instrument(console: &str, name: &str, times: &Times) -> Result<Vec<u8>, String> {
+    let (kind, max_volume) = match console {
+        "NDS" => (TYPE_NDS, 127),
+        "GBA" => (TYPE_GBA_DMA, 255),
+        _ => return Err("Furnace instruments can only be made for NDS or GBA".to_string()),
+    };
+    // Slow the macro down until it fits
+    let mut speed = 1;
+    let (steps, release_point) = loop {
+        let (steps, release_point) = volume_macro(times, max_volume, speed);
+        if steps.len() <= MAX_MACRO_LENGTH || speed == u8::MAX as u32 {
+            break (steps, release_point.min(MAX_MACRO_LENGTH - 1));
+        }
+        speed += 1;
+    };
+    let steps = &steps[..steps.len().min(MAX_MACRO_LENGTH)];
+
+    let mut macros = MACRO_HEADER_LENGTH.to_le_bytes().to_vec();
+    // Code, length, loop, release, mode, sequence of bytes, delay, speed
+    macros.extend([
+        MACRO_VOLUME,
+        steps.len() as u8,
+        NONE,
+        release_point as u8,
+        0,
+        0,
+        0,
+        speed as u8,
+    ]);
+    macros.extend(steps);
+    macros.push(MACRO_END);
+
+    let mut text = name.as_bytes().to_vec();
+    text.push(0);
+
+    let mut fui = b"FINS".to_vec();
+    fui.extend(VERSION.to_le_bytes());
+    fui.extend([kind, 0]);
+    fui.extend(feature(b"NA", &text));
+    fui.extend(feature(b"MA", &macros));
+    fui.extend(b"EN");
+    Ok(fui)
+}
+
+/// The volume macro's steps, speed and release point
+fn read_volume_macro(data: &[u8]) -> Option<(Vec<u8>, u32, Option<usize>)> {
+    let header_length = u16::from_le_bytes(data.get(..2)?.try_into().ok()?) as usize;
+    let mut position = 2;
+    while position < data.len() {
+        let header = data.get(position..position + header_length.max(8))?;
+        let (code, length, release, word_size) =
+            (header[0], header[1] as usize, header[3], header[5] >> 6);
+        if code == MACRO_END {
+            return None;
+        }
+        let width = [1, 1, 2, 4][word_size as usize];
+        let start = position + header_length;
+        let end = start + length * width;
+        if code == MACRO_VOLUME {
+            let steps = data
+                .get(start..end)?
+                .chunks(width)
+                .map(|word| match word_size {
+                    2 => i16::from_le_bytes([word[0], word[1]]) as i64,
+                    3 => i32::from_le_bytes([word[0], word[1], word[2], word[3]]) as i64,
+                    1 => word[0] as i8 as i64,
+                    _ => word[0] as i64,
+                })
+                .map(|step| step.clamp(0, 255) as u8)
+                .collect();
+            let release = (release != NONE).then_some(release as usize);
+            return Some((steps, header[7].max(1) as u32, release));
+        }
+        position = end;
+    }
+    None
+}
+
+/// The model id for the instrument's chip and its volume macro as SF2 times
+pub fn read(fui: &[u8]) -> Result<(&'static str, Times), String> {
+    if fui.get(..4) != Some(b"FINS") || fui.len() < 8 {
+        return Err("Not a Furnace instrument, or one from before Furnace 0.6".to_string());
+    }
+    let (max_volume, id) = CHIPS
+        .iter()
+        .find(|(kind, _, _)| *kind == fui[6])
+        .map(|&(_, max_volume, id)| (max_volume, id))
+        .ok_or("Only NDS and GBA DirectSound instruments can be read")?;
+    let mut position = 8;
+    let mut volume_macro = None;
+    while let Some(code) = fui.get(position..position + 2) {
+        if code == b"EN" {
+            break;
+        }
+        let length = fui
+            .get(position + 2..position + 4)
+            .map(|length| u16::from_le_bytes([length[0], length[1]]) as usize)
+            .ok_or("The instrument is cut off")?;
+        let data = fui
+            .get(position + 4..position + 4 + length)
+            .ok_or("The instrument is cut off")?;
+        if code == b"MA" {
+            volume_macro = read_volume_macro(data);
+        }
+        position += 4 + length;
+    }
+    let (steps, speed, release) = volume_macro.ok_or("The instrument has no volume macro")?;
+    if steps.is_empty() {
+        return Err("The volume macro is empty".to_string());
+    }
+    Ok((id, times(&steps, speed, release, max_volume)))
+}
+
+/// Measures a volume macro the way `volume_macro` draws one
+fn times(steps: &[u8], speed: u32, release: Option<usize>, max_volume: u8) -> Times {
+    let tick = speed as f64 / TICKS_PER_SECOND;
+    let sustain_point = release.unwrap_or(steps.len() - 1).min(steps.len() - 1);
+    let before_release = &steps[..=sustain_point];
+    let peak = before_release.iter().copied().max().unwrap_or(0);
+    let peak_point = before_release
+        .iter()
+        .position(|&step| step == peak)
+        .unwrap_or(0);
+    let peak_decibels = decibels(peak, max_volume);
+    let sustain = decibels(steps[sustain_point], max_volume);
+    let silence = silence(max_volume);
+
+    // Stretch the time a stage takes over its drop to the full 144 dB
+    let full_range = |ticks: usize, drop: f64| {
+        if ticks == 0 || drop <= 0.0 {
+            0.0
+        } else {
+            ticks as f64 * tick * 144.0 / drop
+        }
+    };
+    let decay_ticks = before_release[peak_point..]
+        .iter()
+        .position(|&step| step <= steps[sustain_point])
+        .unwrap_or(0);
+    let release_ticks = steps.len() - 1 - sustain_point;
+    let end = decibels(*steps.last().unwrap_or(&0), max_volume);
+    Times {
+        attack: peak_point as f64 * tick,
+        hold: 0.0,
+        decay: full_range(decay_ticks, sustain - peak_decibels),
+        sustain: if steps[sustain_point] == 0 {
+            144.0
+        } else {
+            sustain
+        },
+        release: if release.is_some() {
+            full_range(release_ticks, end.min(silence) - sustain)
+        } else {
+            0.0
+        },
+    }
+}
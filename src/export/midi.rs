@@ -0,0 +1,98 @@
+//! Envelopes as MIDI controller changes relative to a General MIDI instrument's own envelope
+//!
+//! GS and XG share the NRPNs for attack, decay and release time, with 64 meaning no change; GM2
+//! uses sound controllers 73, 75 and 72 the same way. Neither has a sustain. The standards don't
+//! pin the offsets to times, so a step of 10 is taken as doubling or halving the time, which is
+//! about what Roland and Yamaha modules do.
+
+use crate::model::Times;
+
+const STEPS_PER_DOUBLING: f64 = 10.0;
+
+/// Attack, decay and release in seconds, roughly as GM sets play each family of 8 programs
+const FAMILY_ENVELOPES: [(&str, [f64; 3]); 16] = [
+    ("Piano", [0.002, 8.0, 0.6]),
+    ("Chromatic Percussion", [0.002, 3.0, 0.8]),
+    ("Organ", [0.01, 1.0, 0.1]),
+    ("Guitar", [0.002, 5.0, 0.5]),
+    ("Bass", [0.002, 4.0, 0.2]),
+    ("Strings", [0.1, 1.0, 0.6]),
+    ("Ensemble", [0.2, 1.0, 0.8]),
+    ("Brass", [0.05, 1.0, 0.2]),
+    ("Reed", [0.03, 1.0, 0.2]),
+    ("Pipe", [0.05, 1.0, 0.2]),
+    ("Synth Lead", [0.005, 1.0, 0.2]),
+    ("Synth Pad", [0.5, 2.0, 1.5]),
+    ("Synth Effects", [0.3, 2.0, 1.5]),
+    ("Ethnic", [0.01, 2.0, 0.5]),
+    ("Percussive", [0.002, 1.0, 0.3]),
+    ("Sound Effects", [0.01, 1.0, 0.5]),
+];
+
+/// Stage, GS/XG NRPN LSB and GM2 controller
+const STAGES: [(&str, u8, u8); 3] = [
+    ("attack", 0x63, 73),
+    ("decay", 0x64, 75),
+    ("release", 0x66, 72),
+];
+
+/// Offset from the default, limited to what the standard allows either side of 64
+fn offset(time: f64, default: f64, limit: i32) -> i32 {
+    if time <= 0.0 {
+        -limit
+    } else {
+        ((STEPS_PER_DOUBLING * (time / default).log2()).round() as i32).clamp(-limit, limit)
+    }
+}
+
+fn event(status: u8, channel: u8, data: &[u8]) -> String {
+    let mut event = format!("{:02X}", status | channel);
+    for byte in data {
+        event += &format!(" {:02X}", byte);
+    }
+    event
+}
+
+/// Program change, then the GS/XG NRPNs and the GM2 controllers for the envelope, as hex
+/// messages for channel 0-15. Program is 0-127.
+pub fn describe(times: &Times, program: u8, channel: u8) -> String {
+    let (family, defaults) = FAMILY_ENVELOPES[(program as usize / 8).min(15)];
+    let program_change = event(0xC0, channel, &[program]);
+    let times = [times.attack, times.decay, times.release];
+
+    let mut result = format!(
+        "Program {} ({}), channel {}: {}",
+        program + 1,
+        family,
+        channel + 1,
+        program_change
+    );
+    result += " \nGS/XG NRPN:";
+    for (i, (stage, lsb, _)) in STAGES.iter().enumerate() {
+        let offset = offset(times[i], defaults[i], 50);
+        result += &format!(
+            " \n{}: {}, {}, {} ({:+})",
+            stage,
+            event(0xB0, channel, &[99, 0x01]),
+            event(0xB0, channel, &[98, *lsb]),
+            event(0xB0, channel, &[6, (64 + offset) as u8]),
+            offset
+        );
+    }
+    result += &format!(
+        " \nNRPN reset: {}, {}",
+        event(0xB0, channel, &[99, 0x7F]),
+        event(0xB0, channel, &[98, 0x7F])
+    );
+    result += " \nGM2:";
+    for (i, (stage, _, controller)) in STAGES.iter().enumerate() {
+        let offset = offset(times[i], defaults[i], 64);
+        result += &format!(
+            " \n{}: {} ({:+})",
+            stage,
+            event(0xB0, channel, &[*controller, (64 + offset).min(127) as u8]),
+            offset
+        );
+    }
+    result
+}
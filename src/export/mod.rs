@@ -0,0 +1,159 @@
+//! Instrument and bank formats, mostly written but some read back in
+
+pub mod decent_sampler;
+pub mod dls;
+pub mod furnace;
+pub mod midi;
+pub mod nitro;
+pub mod sf2;
+pub mod sfz;
+pub mod tracker;
+
+use std::{collections::HashMap, fs, path::Path};
+
+use crate::model::{EnvelopeModel, Values, NDS};
+use crate::port;
+use crate::rom::direct_sound;
+
+/// A sample and the envelope it's played with, in the console's values
+pub struct Instrument {
+    pub sample: String,
+    pub values: Values,
+}
+
+/// The instruments in a text file, one per line with the envelope as the last four numbers, as in
+/// a voicegroup.inc file or values copied from a bank editor. The last word before them is taken
+/// as the sample name, skipping assembler directives. Lines without an envelope are skipped.
+pub fn read_instruments(model: &dyn EnvelopeModel, text: &str) -> Vec<Instrument> {
+    let mut instruments = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line
+            .split(|c: char| c == ',' || c.is_ascii_whitespace())
+            .filter(|word| !word.is_empty())
+            .collect();
+        let Some(split) = words.len().checked_sub(4) else {
+            continue;
+        };
+        let envelope: Vec<u8> = words[split..]
+            .iter()
+            .filter_map(|word| word.parse::<u8>().ok())
+            .map(|value| model.scale_pasted(value).min(model.max_value()))
+            .collect();
+        let [attack, decay, sustain, release] = envelope[..] else {
+            continue;
+        };
+        let sample = words[..split]
+            .iter()
+            .rev()
+            .find(|word| word.parse::<f64>().is_err() && !word.starts_with('.'))
+            .map(|word| word.to_string())
+            .unwrap_or(format!("instrument_{}", number + 1));
+        instruments.push(Instrument {
+            sample,
+            values: Values {
+                attack,
+                decay,
+                sustain,
+                release,
+                parameters: vec![0; model.parameters().len()],
+                ..Values::default()
+            },
+        });
+    }
+    instruments
+}
+
+/// Text made safe for an XML attribute
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// The instruments in a Nitro Studio 2 bank XML, or in a text file read by `read_instruments`
+pub fn read_bank(model: &dyn EnvelopeModel, path: &Path) -> Result<Vec<Instrument>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("xml"))
+    {
+        if model.id() != NDS.id() {
+            return Err("Nitro Studio 2 banks hold NDS values".to_string());
+        }
+        nitro::read(&text)
+    } else {
+        Ok(read_instruments(model, &text))
+    }
+}
+
+/// Writes a bank in the format of the file's extension. Nitro Studio 2 banks have to exist already
+/// and only have their envelopes replaced, from envelope `first` on, and envelopes from other
+/// consoles are ported to NDS for them. SF2 samples come from the `decomp` checkout if it's given.
+pub fn save(
+    model: &dyn EnvelopeModel,
+    instruments: &[Instrument],
+    path: &Path,
+    first: usize,
+    decomp: Option<&Path>,
+) -> Result<(), String> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let data = match extension.as_str() {
+        "sfz" => sfz::bank(model, instruments).into_bytes(),
+        "dls" => dls::bank(model, instruments),
+        "sf2" => {
+            // Samples are looked up by name in a decomp checkout if there's one
+            let mut waves = match decomp {
+                Some(root) => direct_sound::decomp_samples(root)?,
+                None => HashMap::new(),
+            };
+            let (presets, samples) = sf2::instruments(model, instruments, &mut waves);
+            sf2::write(model.name(), &presets, &samples)?
+        }
+        "xml" => {
+            let instruments: Vec<Instrument> = instruments
+                .iter()
+                .map(|instrument| Instrument {
+                    sample: instrument.sample.clone(),
+                    values: if model.id() == NDS.id() {
+                        instrument.values.clone()
+                    } else {
+                        port::port(model, &NDS, &instrument.values).values
+                    },
+                })
+                .collect();
+            let bank = fs::read_to_string(path).map_err(|_| {
+                "Save a bank from Nitro Studio 2 first, its envelopes are written into it"
+                    .to_string()
+            })?;
+            nitro::rewrite(&bank, &instruments, first)?.into_bytes()
+        }
+        "dspreset" => decent_sampler::preset(model, instruments).into_bytes(),
+        "fui" => match instruments {
+            [instrument] => furnace::instrument(
+                model.console(),
+                &instrument.sample,
+                &model.to_times(&instrument.values),
+            )?,
+            _ => return Err("A .fui file holds a single instrument".to_string()),
+        },
+        "xi" | "iti" => match instruments {
+            [instrument] if extension == "xi" => {
+                tracker::xi(&instrument.sample, &model.curve(&instrument.values))
+            }
+            [instrument] => tracker::iti(&instrument.sample, &model.curve(&instrument.values)),
+            _ => return Err(format!("A .{} file holds a single instrument", extension)),
+        },
+        _ => {
+            return Err(format!(
+                "Can't save .{} files, use .sfz, .sf2, .dls, .dspreset, .xml, .fui, .xi or .iti",
+                extension
+            ))
+        }
+    };
+    fs::write(path, data).map_err(|e| e.to_string())
+}
@@ -0,0 +1,100 @@
+//! Nitro Studio 2 bank XML
+//!
+//! Any element with `Attack`, `Decay`, `Sustain` and `Release` attributes is an envelope, so
+//! banks are read whatever else they hold. Rewriting a bank only touches those four values and
+//! leaves the rest of the file as it was, so banks are only written into ones Nitro Studio 2
+//! saved.
+
+use roxmltree::{Document, Node};
+
+use crate::model::Values;
+
+use super::Instrument;
+
+const STAGES: [&str; 4] = ["attack", "decay", "sustain", "release"];
+
+fn attribute<'a>(node: &Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|a| a.name().eq_ignore_ascii_case(name))
+        .map(|a| a.value())
+}
+
+fn envelopes<'a, 'input>(document: &'a Document<'input>) -> Vec<Node<'a, 'input>> {
+    document
+        .descendants()
+        .filter(|node| STAGES.iter().all(|stage| attribute(node, stage).is_some()))
+        .collect()
+}
+
+/// The element's name attribute, or its wave number
+fn sample(node: &Node, number: usize) -> String {
+    if let Some(name) = attribute(node, "name") {
+        return name.to_string();
+    }
+    node.attributes()
+        .find(|a| a.name().to_lowercase().contains("wave"))
+        .map(|a| format!("wave_{}", a.value()))
+        .unwrap_or(format!("instrument_{}", number + 1))
+}
+
+/// Every envelope in the bank, in NDS values
+pub fn read(xml: &str) -> Result<Vec<Instrument>, String> {
+    let document = Document::parse(xml).map_err(|e| e.to_string())?;
+    let mut instruments = Vec::new();
+    for (number, node) in envelopes(&document).iter().enumerate() {
+        let value = |stage: &str| -> Result<u8, String> {
+            let text = attribute(node, stage).unwrap_or("");
+            text.trim()
+                .parse::<u8>()
+                .map(|value| value.min(127))
+                .map_err(|_| format!("{} \"{}\" isn't a value from 0 to 127", stage, text))
+        };
+        instruments.push(Instrument {
+            sample: sample(node, number),
+            values: Values {
+                attack: value("attack")?,
+                decay: value("decay")?,
+                sustain: value("sustain")?,
+                release: value("release")?,
+                ..Values::default()
+            },
+        });
+    }
+    Ok(instruments)
+}
+
+/// The bank with its envelopes replaced in order, starting from envelope `first` (counted from 0)
+/// and leaving the others as they were
+pub fn rewrite(xml: &str, instruments: &[Instrument], first: usize) -> Result<String, String> {
+    let document = Document::parse(xml).map_err(|e| e.to_string())?;
+    let nodes = envelopes(&document);
+    if first + instruments.len() > nodes.len() {
+        return Err(format!(
+            "The bank has {} envelopes, too few for {} instruments from envelope {}",
+            nodes.len(),
+            instruments.len(),
+            first
+        ));
+    }
+    let mut replacements = Vec::new();
+    for (node, instrument) in nodes[first..].iter().zip(instruments) {
+        let values = &instrument.values;
+        for attribute in node.attributes() {
+            let value = match attribute.name().to_lowercase().as_str() {
+                "attack" => values.attack,
+                "decay" => values.decay,
+                "sustain" => values.sustain,
+                "release" => values.release,
+                _ => continue,
+            };
+            replacements.push((attribute.range_value(), value.to_string()));
+        }
+    }
+    // Back to front so the earlier ranges stay put
+    replacements.sort_by_key(|(range, _)| std::cmp::Reverse(range.start));
+    let mut xml = xml.to_string();
+    for (range, value) in replacements {
+        xml.replace_range(range, &value);
+    }
+    Ok(xml)
+}
@@ -0,0 +1,480 @@
+//! SoundFont 2 banks built from whole console instruments
+//!
+//! Every region of an instrument becomes an instrument zone with its key range, root key, pan and
+//! envelope. Samples are decoded from the game or a decomp checkout where possible, anything else
+//! (PSG channels, or a wave that can't be found) gets a silent placeholder named after what it
+//! stands for, to be replaced in an SF2 editor.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::model::{nds_attenuation, nds_pan, EnvelopeModel, Times};
+use crate::rom::{direct_sound, gba, sdat, Wave};
+
+use super::Instrument;
+
+const GEN_PAN: u16 = 17;
+const GEN_ATTACK: u16 = 34;
+const GEN_HOLD: u16 = 35;
+const GEN_DECAY: u16 = 36;
+const GEN_SUSTAIN: u16 = 37;
+const GEN_RELEASE: u16 = 38;
+const GEN_INSTRUMENT: u16 = 41;
+const GEN_KEY_RANGE: u16 = 43;
+const GEN_ATTENUATION: u16 = 48;
+const GEN_SAMPLE: u16 = 53;
+const GEN_SAMPLE_MODES: u16 = 54;
+const GEN_ROOT_KEY: u16 = 58;
+
+/// Timecents range of the volume envelope generators
+const MIN_TIMECENTS: f64 = -12000.0;
+const MAX_TIMECENTS: f64 = 8000.0;
+
+/// Zero samples the format requires after every sample
+const SAMPLE_PADDING: usize = 46;
+/// Samples, instruments, zones and generators are counted with 16-bit indices
+const MAX_INDEX: usize = u16::MAX as usize;
+const PLACEHOLDER_SAMPLES: usize = 16;
+const PLACEHOLDER_RATE: u32 = 22050;
+
+/// 16-bit mono PCM
+pub struct Sample {
+    pub name: String,
+    pub rate: u32,
+    pub data: Vec<i16>,
+    /// Where the loop starts, looping until the end
+    pub loop_start: Option<u32>,
+}
+
+impl Sample {
+    /// A decoded wave, or a placeholder if there's none
+    pub fn new(name: String, wave: Option<Wave>) -> Sample {
+        match wave {
+            Some(wave) => Sample {
+                name,
+                rate: wave.rate,
+                data: wave.data,
+                loop_start: wave.loop_start,
+            },
+            None => Sample::placeholder(name),
+        }
+    }
+
+    pub fn placeholder(name: String) -> Sample {
+        Sample {
+            name,
+            rate: PLACEHOLDER_RATE,
+            data: vec![0; PLACEHOLDER_SAMPLES],
+            loop_start: None,
+        }
+    }
+}
+
+pub struct Zone {
+    pub low_key: u8,
+    pub high_key: u8,
+    pub root_key: u8,
+    /// -500 for left to 500 for right
+    pub pan: i16,
+    /// Centibels quieter than full volume
+    pub attenuation: f64,
+    pub times: Times,
+    /// Index into the bank's samples
+    pub sample: usize,
+}
+
+pub struct Preset {
+    pub name: String,
+    pub bank: u16,
+    pub program: u16,
+    pub zones: Vec<Zone>,
+}
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = id.to_vec();
+    chunk.extend((data.len() as u32).to_le_bytes());
+    chunk.extend(data);
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+    chunk
+}
+
+fn list(kind: &[u8; 4], children: &[u8]) -> Vec<u8> {
+    let mut data = kind.to_vec();
+    data.extend(children);
+    chunk(b"LIST", &data)
+}
+
+/// A name cut or padded to the 20 bytes of a header
+fn name(name: &str) -> [u8; 20] {
+    let mut bytes = [0; 20];
+    for (byte, c) in bytes.iter_mut().zip(name.bytes().take(19)) {
+        *byte = c;
+    }
+    bytes
+}
+
+fn timecents(seconds: f64) -> i16 {
+    if seconds <= 0.0 {
+        MIN_TIMECENTS as i16
+    } else {
+        (1200.0 * seconds.log2())
+            .round()
+            .clamp(MIN_TIMECENTS, MAX_TIMECENTS) as i16
+    }
+}
+
+fn generator(data: &mut Vec<u8>, operator: u16, amount: i16) {
+    data.extend(operator.to_le_bytes());
+    data.extend(amount.to_le_bytes());
+}
+
+/// The instrument and sample generators take an unsigned index
+fn index_generator(data: &mut Vec<u8>, operator: u16, index: usize) {
+    data.extend(operator.to_le_bytes());
+    data.extend((index as u16).to_le_bytes());
+}
+
+fn zone_generators(zone: &Zone, looped: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    // The key range has to come first and the sample last
+    data.extend(GEN_KEY_RANGE.to_le_bytes());
+    data.extend([zone.low_key, zone.high_key]);
+    generator(&mut data, GEN_PAN, zone.pan.clamp(-500, 500));
+    let attenuation = zone.attenuation.round().clamp(0.0, 1440.0);
+    if attenuation > 0.0 {
+        generator(&mut data, GEN_ATTENUATION, attenuation as i16);
+    }
+    generator(&mut data, GEN_ATTACK, timecents(zone.times.attack));
+    generator(&mut data, GEN_HOLD, timecents(zone.times.hold));
+    generator(&mut data, GEN_DECAY, timecents(zone.times.decay));
+    let sustain = (zone.times.sustain.abs() * 10.0).round().min(1440.0);
+    generator(&mut data, GEN_SUSTAIN, sustain as i16);
+    generator(&mut data, GEN_RELEASE, timecents(zone.times.release));
+    if looped {
+        generator(&mut data, GEN_SAMPLE_MODES, 1);
+    }
+    generator(&mut data, GEN_ROOT_KEY, zone.root_key as i16);
+    index_generator(&mut data, GEN_SAMPLE, zone.sample);
+    data
+}
+
+fn bag(data: &mut Vec<u8>, generator: usize) {
+    data.extend((generator as u16).to_le_bytes());
+    data.extend(0_u16.to_le_bytes());
+}
+
+/// Writes the presets, each with an instrument of the same name, and their samples
+pub fn write(bank_name: &str, presets: &[Preset], samples: &[Sample]) -> Result<Vec<u8>, String> {
+    if samples.len() > MAX_INDEX || presets.len() > MAX_INDEX {
+        return Err(format!(
+            "An SF2 holds up to {} samples and instruments, this bank has {} and {}",
+            MAX_INDEX,
+            samples.len(),
+            presets.len()
+        ));
+    }
+    let mut info = chunk(b"ifil", &[2, 0, 1, 0]);
+    info.extend(chunk(b"isng", b"EMU8000\0"));
+    info.extend(chunk(b"INAM", &[bank_name.as_bytes(), &[0]].concat()));
+
+    let mut pcm = Vec::new();
+    let mut sample_headers = Vec::new();
+    for sample in samples {
+        let start = (pcm.len() / 2) as u32;
+        for value in sample.data.iter().chain(&[0; SAMPLE_PADDING]) {
+            pcm.extend(value.to_le_bytes());
+        }
+        let end = start + sample.data.len() as u32;
+        let loop_start = start + sample.loop_start.unwrap_or(0);
+        sample_headers.extend(name(&sample.name));
+        for field in [start, end, loop_start, end, sample.rate] {
+            sample_headers.extend(field.to_le_bytes());
+        }
+        // Original pitch 60, no correction or link, mono
+        sample_headers.extend([60, 0, 0, 0, 1, 0]);
+    }
+    sample_headers.extend(name("EOS"));
+    sample_headers.extend([0; 26]);
+
+    let (mut phdr, mut pbag, mut pgen) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut inst, mut ibag, mut igen) = (Vec::new(), Vec::new(), Vec::new());
+    for (index, preset) in presets.iter().enumerate() {
+        phdr.extend(name(&preset.name));
+        phdr.extend(preset.program.to_le_bytes());
+        phdr.extend(preset.bank.to_le_bytes());
+        phdr.extend(((pbag.len() / 4) as u16).to_le_bytes());
+        phdr.extend([0; 12]);
+        bag(&mut pbag, pgen.len() / 4);
+        index_generator(&mut pgen, GEN_INSTRUMENT, index);
+
+        inst.extend(name(&preset.name));
+        inst.extend(((ibag.len() / 4) as u16).to_le_bytes());
+        for zone in &preset.zones {
+            bag(&mut ibag, igen.len() / 4);
+            let looped = samples
+                .get(zone.sample)
+                .is_some_and(|sample| sample.loop_start.is_some());
+            igen.extend(zone_generators(zone, looped));
+        }
+    }
+    // The terminal records point one past the last bag and generator
+    for (records, count) in [
+        ("preset zones", pbag.len() / 4),
+        ("instrument zones", ibag.len() / 4),
+        ("generators", igen.len() / 4),
+    ] {
+        if count > MAX_INDEX {
+            return Err(format!(
+                "The bank needs {} {}, more than an SF2 holds",
+                count, records
+            ));
+        }
+    }
+    phdr.extend(name("EOP"));
+    phdr.extend([0, 0, 0, 0]);
+    phdr.extend(((pbag.len() / 4) as u16).to_le_bytes());
+    phdr.extend([0; 12]);
+    bag(&mut pbag, pgen.len() / 4);
+    inst.extend(name("EOI"));
+    inst.extend(((ibag.len() / 4) as u16).to_le_bytes());
+    bag(&mut ibag, igen.len() / 4);
+    // Terminal generators and modulators
+    pgen.extend([0; 4]);
+    igen.extend([0; 4]);
+
+    let mut hydra = chunk(b"phdr", &phdr);
+    hydra.extend(chunk(b"pbag", &pbag));
+    hydra.extend(chunk(b"pmod", &[0; 10]));
+    hydra.extend(chunk(b"pgen", &pgen));
+    hydra.extend(chunk(b"inst", &inst));
+    hydra.extend(chunk(b"ibag", &ibag));
+    hydra.extend(chunk(b"imod", &[0; 10]));
+    hydra.extend(chunk(b"igen", &igen));
+    hydra.extend(chunk(b"shdr", &sample_headers));
+
+    let mut data = b"sfbk".to_vec();
+    data.extend(list(b"INFO", &info));
+    data.extend(list(b"sdta", &chunk(b"smpl", &pcm)));
+    data.extend(list(b"pdta", &hydra));
+    Ok(chunk(b"RIFF", &data))
+}
+
+/// What an SBNK note plays, for naming its placeholder
+fn note_sample(bank: &sdat::Bank, note: &sdat::Note) -> String {
+    match note.kind {
+        2 => format!("PSG duty {}", note.wave),
+        3 => "Noise".to_string(),
+        _ => {
+            let archive = bank.wave_archives.get(note.archive as usize);
+            format!(
+                "SWAR {} wave {}",
+                archive.unwrap_or(&note.archive),
+                note.wave
+            )
+        }
+    }
+}
+
+/// The bank's instruments as presets, with every sample they use from the archive's SWARs, played
+/// at an NDS volume such as a sequence's
+pub fn sbnk(
+    model: &dyn EnvelopeModel,
+    archive: &sdat::Sdat,
+    bank: &sdat::Bank,
+    volume: u8,
+) -> (Vec<Preset>, Vec<Sample>) {
+    let mut samples: BTreeMap<String, usize> = BTreeMap::new();
+    let mut sample_list = Vec::new();
+    let mut zone = |low_key: u8, high_key: u8, note: &sdat::Note| {
+        let name = note_sample(bank, note);
+        let sample = *samples.entry(name.clone()).or_insert_with(|| {
+            let wave = bank
+                .wave_archives
+                .get(note.archive as usize)
+                .filter(|_| note.kind == 1)
+                .and_then(|&swar| archive.wave(swar, note.wave));
+            sample_list.push(Sample::new(name, wave));
+            sample_list.len() - 1
+        });
+        Zone {
+            low_key,
+            high_key,
+            root_key: note.root_key,
+            pan: nds_pan(note.pan),
+            attenuation: nds_attenuation(volume),
+            times: model.to_times(&note.values),
+            sample,
+        }
+    };
+    let mut presets = Vec::new();
+    for (program, instrument) in &bank.instruments {
+        let zones = match instrument {
+            sdat::Instrument::Single(note) => vec![zone(0, 127, note)],
+            sdat::Instrument::Drums { low, notes } => notes
+                .iter()
+                .enumerate()
+                .map(|(i, note)| {
+                    let key = low.saturating_add(i as u8).min(127);
+                    zone(key, key, note)
+                })
+                .collect(),
+            sdat::Instrument::KeySplit(regions) => {
+                let mut low = 0;
+                let mut zones = Vec::new();
+                for (high, note) in regions {
+                    zones.push(zone(low, (*high).min(127), note));
+                    low = high.saturating_add(1);
+                }
+                zones
+            }
+        };
+        presets.push(Preset {
+            name: format!("Program {}", program),
+            bank: program / 128,
+            program: program % 128,
+            zones,
+        });
+    }
+    (presets, sample_list)
+}
+
+/// Instruments read from a text file, such as a decomp voicegroup, each playing the sample of
+/// its name from `waves` over every key
+pub fn instruments(
+    model: &dyn EnvelopeModel,
+    instruments: &[Instrument],
+    waves: &mut HashMap<String, Wave>,
+) -> (Vec<Preset>, Vec<Sample>) {
+    let mut samples: BTreeMap<String, usize> = BTreeMap::new();
+    let mut sample_list = Vec::new();
+    let mut presets = Vec::new();
+    for (program, instrument) in instruments.iter().enumerate() {
+        let name = &instrument.sample;
+        // Decomp labels all start the same, which would use up the 19 characters of a name
+        let short_name = name.trim_start_matches("DirectSoundWaveData_").to_string();
+        let sample = *samples.entry(name.clone()).or_insert_with(|| {
+            sample_list.push(Sample::new(short_name.clone(), waves.remove(name)));
+            sample_list.len() - 1
+        });
+        presets.push(Preset {
+            name: short_name,
+            bank: (program / 128) as u16,
+            program: (program % 128) as u16,
+            zones: vec![Zone {
+                low_key: 0,
+                high_key: 127,
+                root_key: 60,
+                pan: 0,
+                attenuation: 0.0,
+                times: model.to_times(&instrument.values),
+                sample,
+            }],
+        });
+    }
+    (presets, sample_list)
+}
+
+/// What an MP2K tone plays, for naming its placeholder
+fn tone_sample(tone: &gba::Tone) -> String {
+    // Names are cut off at 19 characters
+    match tone.kind {
+        gba::ToneKind::DirectSound => format!("DS 0x{:08X}", tone.data),
+        gba::ToneKind::Wave => format!("Wave 0x{:08X}", tone.data),
+        gba::ToneKind::Noise => format!("Noise {}", tone.data),
+        _ => format!("{} duty {}", tone.kind.name(), tone.data),
+    }
+}
+
+/// MP2K voices are centred unless the pan's top bit is set, with 0xC0 in the middle
+fn mp2k_pan(pan: u8) -> i16 {
+    if pan & 0x80 == 0 {
+        0
+    } else {
+        ((pan & 0x7F) as i16 - 64) * 500 / 64
+    }
+}
+
+/// The voicegroup's voices as presets, with keysplits and drums resolved into a zone for every
+/// range of keys playing the same tone. DirectSound envelopes go through `direct` and the Game
+/// Boy channels' through `cgb`.
+pub fn voicegroup(
+    direct: &dyn EnvelopeModel,
+    cgb: &dyn EnvelopeModel,
+    rom: &[u8],
+    scan: &gba::Scan,
+    address: u32,
+) -> (Vec<Preset>, Vec<Sample>) {
+    let mut samples: BTreeMap<String, usize> = BTreeMap::new();
+    let mut sample_list = Vec::new();
+    let mut zone = |low_key: u8, high_key: u8, root_key: u8, tone: &gba::Tone| {
+        let name = tone_sample(tone);
+        let sample = *samples.entry(name.clone()).or_insert_with(|| {
+            let wave = gba::offset(rom, tone.data)
+                .filter(|_| tone.kind == gba::ToneKind::DirectSound)
+                .and_then(|at| direct_sound::read(&rom[at..]));
+            sample_list.push(Sample::new(name, wave));
+            sample_list.len() - 1
+        });
+        let model = match tone.kind {
+            gba::ToneKind::DirectSound => direct,
+            _ => cgb,
+        };
+        Zone {
+            low_key,
+            high_key,
+            root_key,
+            pan: mp2k_pan(tone.pan),
+            attenuation: 0.0,
+            times: model.to_times(&tone.values),
+            sample,
+        }
+    };
+    let mut presets = Vec::new();
+    let programs = scan
+        .voicegroups
+        .get(&address)
+        .map_or(&[][..], |voicegroup| &voicegroup.voices);
+    for (program, voice) in programs {
+        let zones = match voice {
+            gba::Voice::Tone(tone) => vec![zone(0, 127, 60, tone)],
+            gba::Voice::Keysplit { .. } | gba::Voice::Drums { .. } => {
+                let drums = matches!(voice, gba::Voice::Drums { .. });
+                let mut zones = Vec::new();
+                let mut key = 0;
+                while key < 128 {
+                    let Some(tone) = scan.tone(rom, address, *program, key) else {
+                        key += 1;
+                        continue;
+                    };
+                    let mut high = key;
+                    while high < 127
+                        && scan
+                            .tone(rom, address, *program, high + 1)
+                            .is_some_and(|next| std::ptr::eq(next, tone))
+                    {
+                        high += 1;
+                    }
+                    // Drums play every key at their own note
+                    let root = if drums {
+                        (60 + key as i16 - tone.key as i16).clamp(0, 127) as u8
+                    } else {
+                        60
+                    };
+                    zones.push(zone(key, high, root, tone));
+                    key = high + 1;
+                }
+                zones
+            }
+        };
+        if !zones.is_empty() {
+            presets.push(Preset {
+                name: format!("Program {}", program),
+                bank: 0,
+                program: *program as u16,
+                zones,
+            });
+        }
+    }
+    (presets, sample_list)
+}
@@ -0,0 +1,45 @@
+//! SFZ, as used by sfizz and Sforzando
+
+use crate::model::{EnvelopeModel, Times};
+
+use super::Instrument;
+
+/// Longest envelope stage SFZ allows, in seconds
+const MAX_TIME: f64 = 100.0;
+
+fn seconds(time: f64) -> f64 {
+    time.min(MAX_TIME)
+}
+
+/// SFZ sustain is a percentage of full volume rather than an attenuation
+pub fn sustain_percent(decibels: f64) -> f64 {
+    100.0 * 10_f64.powf(-decibels.abs() / 20.0)
+}
+
+/// The `ampeg_*` opcodes for an envelope
+pub fn opcodes(times: &Times) -> String {
+    let mut opcodes = format!("ampeg_attack={:.3}", seconds(times.attack));
+    if times.hold > 0.0 {
+        opcodes += &format!(" ampeg_hold={:.3}", seconds(times.hold));
+    }
+    opcodes += &format!(
+        " ampeg_decay={:.3} ampeg_sustain={:.3} ampeg_release={:.3}",
+        seconds(times.decay),
+        sustain_percent(times.sustain),
+        seconds(times.release)
+    );
+    opcodes
+}
+
+/// A whole `.sfz` with a region per instrument
+pub fn bank(model: &dyn EnvelopeModel, instruments: &[Instrument]) -> String {
+    let mut sfz = format!("// {} envelopes\n\n<group>\n", model.name());
+    for instrument in instruments {
+        sfz += &format!(
+            "<region> sample={}.wav {}\n",
+            instrument.sample,
+            opcodes(&model.to_times(&instrument.values))
+        );
+    }
+    sfz
+}
@@ -0,0 +1,180 @@
+//! FastTracker 2 (`.xi`) and Impulse Tracker (`.iti`) instruments, whose envelopes are lists of
+//! points rather than stages
+//!
+//! The console's curve is resampled to tracker ticks and the points that keep it closest are
+//! picked, with the sustain point where the curve reaches the sustain level. Like the DLS export,
+//! the instrument comes with a silent placeholder sample.
+
+use crate::model::Curve;
+
+/// Ticks per second at the default 125 BPM
+const TRACKER_TICKS_PER_SECOND: f64 = 50.0;
+
+/// Envelope volumes go from 0 to 64
+const MAX_VOLUME: f64 = 64.0;
+
+/// Points closer than this to the curve aren't worth adding
+const TOLERANCE: f64 = 0.5;
+
+const XI_POINTS: usize = 12;
+const ITI_POINTS: usize = 25;
+
+const PLACEHOLDER_SAMPLES: usize = 16;
+
+/// An envelope point: the tick and the volume
+type Point = (u16, u8);
+
+/// The curve at tracker ticks, in tracker volume, and where the sustain is
+fn resample(curve: &Curve) -> (Vec<f64>, usize) {
+    let at_ticks = |levels: &[f64]| -> Vec<f64> {
+        let seconds = levels.len() as f64 / curve.ticks_per_second;
+        let ticks = ((seconds * TRACKER_TICKS_PER_SECOND).ceil() as usize).max(1);
+        (0..ticks)
+            .map(|tick| {
+                let position = tick as f64 * curve.ticks_per_second / TRACKER_TICKS_PER_SECOND;
+                levels[(position as usize).min(levels.len() - 1)] * MAX_VOLUME
+            })
+            .collect()
+    };
+    let mut samples = at_ticks(&curve.held);
+    let sustain = samples.len() - 1;
+    // The release starts from the sustain point itself
+    samples.extend(at_ticks(&curve.released).into_iter().skip(1));
+    samples.truncate(u16::MAX as usize + 1);
+    (samples, sustain.min(u16::MAX as usize))
+}
+
+/// The fewest points, up to `max_points`, that stay within the tolerance of the curve
+fn points(curve: &Curve, max_points: usize) -> (Vec<Point>, usize) {
+    let (samples, sustain) = resample(curve);
+    let mut chosen = vec![0, sustain, samples.len() - 1];
+    chosen.dedup();
+    while chosen.len() < max_points {
+        let mut worst = (0.0, 0);
+        for pair in chosen.windows(2) {
+            let (start, end) = (pair[0], pair[1]);
+            for i in start + 1..end {
+                let fraction = (i - start) as f64 / (end - start) as f64;
+                let line = samples[start] + (samples[end] - samples[start]) * fraction;
+                let error = (samples[i] - line).abs();
+                if error > worst.0 {
+                    worst = (error, i);
+                }
+            }
+        }
+        if worst.0 < TOLERANCE {
+            break;
+        }
+        let position = chosen.partition_point(|&i| i < worst.1);
+        chosen.insert(position, worst.1);
+    }
+    let sustain_point = chosen.iter().position(|&i| i == sustain).unwrap_or(0);
+    let points = chosen
+        .iter()
+        .map(|&i| (i as u16, samples[i].round() as u8))
+        .collect();
+    (points, sustain_point)
+}
+
+/// Text padded or cut to a fixed length
+fn fixed(text: &str, length: usize, padding: u8) -> Vec<u8> {
+    let mut bytes: Vec<u8> = text.bytes().filter(|b| b.is_ascii()).take(length).collect();
+    bytes.resize(length, padding);
+    bytes
+}
+
+pub fn xi(name: &str, curve: &Curve) -> Vec<u8> {
+    let (points, sustain) = points(curve, XI_POINTS);
+    let mut xi = b"Extended Instrument: ".to_vec();
+    xi.extend(fixed(name, 22, b' '));
+    xi.push(0x1a);
+    xi.extend(fixed("adsr_calculator", 20, b' '));
+    xi.extend(0x0102_u16.to_le_bytes());
+    // Every note plays sample 0
+    xi.extend([0; 96]);
+    let mut envelope = Vec::new();
+    for (tick, volume) in &points {
+        envelope.extend(tick.to_le_bytes());
+        envelope.extend((*volume as u16).to_le_bytes());
+    }
+    envelope.resize(48, 0);
+    xi.extend(envelope);
+    // No panning envelope
+    xi.extend([0; 48]);
+    xi.extend([points.len() as u8, 0]);
+    // Volume sustain and loop, then panning
+    xi.extend([sustain as u8, 0, 0, 0, 0, 0]);
+    // Volume envelope on with a sustain, panning off
+    xi.extend([0b11, 0]);
+    // No vibrato or fadeout
+    xi.extend([0; 6]);
+    xi.extend([0; 22]);
+    xi.extend(1_u16.to_le_bytes());
+
+    // Sample length, loop start and length, volume, finetune, type, panning, relative note,
+    // reserved, name
+    xi.extend((PLACEHOLDER_SAMPLES as u32).to_le_bytes());
+    xi.extend([0; 8]);
+    xi.extend([64, 0, 0, 128, 0, 0]);
+    xi.extend(fixed(name, 22, b' '));
+    xi.extend([0; PLACEHOLDER_SAMPLES]);
+    xi
+}
+
+pub fn iti(name: &str, curve: &Curve) -> Vec<u8> {
+    let (points, sustain) = points(curve, ITI_POINTS);
+    let mut iti = b"IMPI".to_vec();
+    iti.extend(fixed("", 12, 0));
+    // NNA, duplicate check type and action
+    iti.extend([0, 0, 0, 0]);
+    // No fadeout, pitch-pan separation or center
+    iti.extend([0; 4]);
+    // Global volume, default pan off (bit 7 set), no random variation
+    iti.extend([128, 32 | 0x80, 0, 0]);
+    iti.extend(0x0214_u16.to_le_bytes());
+    iti.extend([1, 0]);
+    iti.extend(fixed(name, 26, 0));
+    // No filter, MIDI channel, program or bank
+    iti.extend([0, 0, 0, 0, 0, 0]);
+    for note in 0..120 {
+        iti.extend([note, 1]);
+    }
+
+    // On with a sustain loop at the sustain point
+    let mut envelope = vec![
+        0b101,
+        points.len() as u8,
+        0,
+        0,
+        sustain as u8,
+        sustain as u8,
+    ];
+    for (tick, volume) in &points {
+        envelope.push(*volume);
+        envelope.extend(tick.to_le_bytes());
+    }
+    envelope.resize(82, 0);
+    iti.extend(envelope);
+    // Panning and pitch envelopes are off
+    iti.extend([0; 82 * 2]);
+    iti.extend([0; 4]);
+
+    let data_offset = iti.len() as u32 + 80;
+    iti.extend(b"IMPS");
+    iti.extend(fixed("", 12, 0));
+    // Global volume, has a sample, volume
+    iti.extend([0, 64, 1, 64]);
+    iti.extend(fixed(name, 26, 0));
+    // Signed, default pan off (bit 7 clear, the opposite of the instrument's)
+    iti.extend([1, 32]);
+    iti.extend((PLACEHOLDER_SAMPLES as u32).to_le_bytes());
+    // Loop start and end, C-5 speed, sustain loop start and end, data offset
+    iti.extend([0; 8]);
+    iti.extend(8363_u32.to_le_bytes());
+    iti.extend([0; 8]);
+    iti.extend(data_offset.to_le_bytes());
+    // No vibrato
+    iti.extend([0; 4]);
+    iti.extend([0; PLACEHOLDER_SAMPLES]);
+    iti
+}
@@ -0,0 +1,159 @@
+//! Modulation: SSEQ and MP2K LFO settings as SF2's vibrato and modulation LFOs
+//!
+//! Both drivers step their LFO once per tick of the model's tick rate. The NDS one reads a sine
+//! table of 128 steps, moving `speed * 64 / 256` steps a tick, and scales it by depth times
+//! range. MP2K's is a triangle wave on a byte counter moving `LFOS` a tick, scaled by `MOD / 128`.
+//! Pitch goes to the vibrato LFO, volume to the modulation LFO, and pan has no SF2 generator so
+//! it's shown for a modulator to be made by hand.
+
+/// SF2 frequencies are absolute cents from 8.176 Hz
+const CENTS_BASE_HZ: f64 = 8.176;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Pitch,
+    Volume,
+    Pan,
+}
+
+/// An LFO in SF2 units
+pub struct Sf2Lfo {
+    pub target: Target,
+    pub frequency: f64,
+    /// Seconds before it starts
+    pub delay: f64,
+    /// Cents for pitch, centibels for volume and tenths of a percent for pan
+    pub depth: f64,
+}
+
+fn target(value: u16) -> Target {
+    match value {
+        1 => Target::Volume,
+        2 => Target::Pan,
+        _ => Target::Pitch,
+    }
+}
+
+/// SSEQ `mod_depth`, `mod_speed`, `mod_type`, `mod_range` and `mod_delay`
+fn nds(values: &[u16], ticks_per_second: f64) -> Sf2Lfo {
+    let [depth, speed, kind, range, delay] = [0, 1, 2, 3, 4].map(|i| values[i] as f64);
+    // Peak of the sine table times depth and range, in 1/256ths
+    let peak = 127.0 * depth * range / 256.0;
+    let target = target(kind as u16);
+    Sf2Lfo {
+        target,
+        frequency: speed * ticks_per_second / 512.0,
+        delay: delay / ticks_per_second,
+        depth: match target {
+            // 1/64ths of a semitone
+            Target::Pitch => peak * 100.0 / 64.0,
+            // Tenths of a decibel, after scaling by 60 / 64
+            Target::Volume => peak * 60.0 / 64.0,
+            // Pan steps, with 64 either side
+            Target::Pan => peak * 500.0 / 64.0,
+        },
+    }
+}
+
+/// MP2K `MOD`, `LFOS`, `MODT` and `LFODL`
+fn mp2k(values: &[u16], ticks_per_second: f64) -> Sf2Lfo {
+    let [depth, speed, kind, delay] = [0, 1, 2, 3].map(|i| values[i] as f64);
+    // The triangle reaches 64, and `MOD * 64 / 128` is how far it moves things
+    let peak = depth / 2.0;
+    let target = target(kind as u16);
+    Sf2Lfo {
+        target,
+        frequency: speed * ticks_per_second / 256.0,
+        delay: delay / ticks_per_second,
+        depth: match target {
+            // Added 16 times over to a pitch in 1/256ths of a semitone
+            Target::Pitch => peak * 16.0 * 100.0 / 256.0,
+            // Volume is multiplied by (128 + modulation) / 128
+            Target::Volume => 200.0 * (128.0 / (128.0 - peak.min(127.0))).log10(),
+            // Added to a pan that goes 128 either side
+            Target::Pan => peak * 500.0 / 128.0,
+        },
+    }
+}
+
+/// A sound driver's LFO settings, by the names its tools use, and their defaults
+pub struct Driver {
+    /// The model whose tick rate the LFO runs at
+    pub model: &'static str,
+    pub fields: &'static [(&'static str, u16)],
+    convert: fn(&[u16], f64) -> Sf2Lfo,
+}
+
+const NDS: Driver = Driver {
+    model: "nds",
+    fields: &[
+        ("Depth", 0),
+        ("Speed", 16),
+        ("Type", 0),
+        ("Range", 1),
+        ("Delay", 0),
+    ],
+    convert: nds,
+};
+
+const MP2K: Driver = Driver {
+    model: "gba",
+    fields: &[("MOD", 0), ("LFOS", 22), ("MODT", 0), ("LFODL", 0)],
+    convert: mp2k,
+};
+
+impl Driver {
+    pub fn convert(&self, values: &[u16], ticks_per_second: f64) -> Sf2Lfo {
+        (self.convert)(values, ticks_per_second)
+    }
+}
+
+/// The driver for a console, None if there's no converter for it
+pub fn driver(console: &str) -> Option<&'static Driver> {
+    match console {
+        "NDS" => Some(&NDS),
+        "GBA" => Some(&MP2K),
+        _ => None,
+    }
+}
+
+fn absolute_cents(hz: f64) -> f64 {
+    if hz <= 0.0 {
+        -16000.0
+    } else {
+        1200.0 * (hz / CENTS_BASE_HZ).log2()
+    }
+}
+
+fn timecents(seconds: f64) -> f64 {
+    if seconds <= 0.0 {
+        -12000.0
+    } else {
+        1200.0 * seconds.log2()
+    }
+}
+
+/// The SF2 generators for an LFO, one per line
+pub fn describe(lfo: &Sf2Lfo) -> String {
+    let (prefix, depth) = match lfo.target {
+        Target::Pitch => ("Vib", format!("vibLfoToPitch: {:.0} cents", lfo.depth)),
+        Target::Volume => ("Mod", format!("modLfoToVolume: {:.1} cB", lfo.depth)),
+        Target::Pan => (
+            "Mod",
+            format!(
+                "pan: {:.0} either side (no generator, route the Mod LFO to pan with a modulator)",
+                lfo.depth
+            ),
+        ),
+    };
+    format!(
+        "{}\nfreq{}LFO: {:.3} Hz ({:.0} cents)\ndelay{}LFO: {:.3} s ({:.0} timecents)",
+        depth,
+        prefix,
+        lfo.frequency,
+        absolute_cents(lfo.frequency),
+        prefix,
+        lfo.delay,
+        timecents(lfo.delay)
+    )
+}
@@ -1,9 +1,7 @@
-#![windows_subsystem = "windows"]
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 extern crate clipboard;
 
-use std::f64::INFINITY;
-
 use iced::{
     widget::{button, column, row, text, text_input},
     Element, Padding, Sandbox, Settings,
@@ -11,20 +9,53 @@ use iced::{
 
 use clipboard::{ClipboardContext, ClipboardProvider};
 
-const TPS_NDS: f64 = 192.0;
-const TPS_GBA: f64 = 59.7275;
-const ZERO_POINT: i32 = -92544;
+mod cli;
+mod export;
+mod lfo;
+mod model;
+mod port;
+mod rom;
+
+use model::{EnvelopeModel, Times, Values};
+
+/// Release builds on Windows have no console of their own, so commands print to the one they
+/// were started from
+#[cfg(windows)]
+fn attach_console() {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    // Fails harmlessly when there's already a console or no parent one
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
 
 fn main() -> iced::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        attach_console();
+        if let Err(e) = cli::run(&args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     App::run(Settings::default())
 }
 
 pub struct App {
-    mode: Mode,
+    models: Vec<Box<dyn EnvelopeModel>>,
+    /// Index into `models`
+    model: usize,
+    /// Converting to the console rather than to SF2
+    putting_in: bool,
     clipboard: Option<ClipboardContext>,
-    attack_table: Vec<i32>,
-    decay_table: Vec<i32>,
-    sustain_table: Vec<i32>,
     attack: u8,
     attack_f: f32,
     attack_input: String,
@@ -41,300 +72,290 @@ pub struct App {
     release_f: f32,
     release_input: String,
     release_result: f64,
+    hold: u8,
+    hold_f: f32,
+    hold_input: String,
+    hold_result: f64,
+    parameters: Vec<u8>,
+    parameter_inputs: Vec<String>,
+    extra_results: Vec<(&'static str, f64)>,
+    /// Model and values of the last port, which are what gets copied
+    ported: Option<(usize, Values)>,
+    /// The result is already in a pasteable format, so it gets copied as shown
+    copy_result: bool,
+    /// Where "Save" writes the envelope, its extension picking the format, and "Load" reads one
+    file_path: String,
+    /// General MIDI program (0-127) that "To MIDI" offsets the envelope from
+    program: u8,
+    program_input: String,
+    /// Which envelope of a Nitro Studio 2 bank a save replaces, from 0
+    entry: usize,
+    entry_input: String,
+    /// Showing the modulation page instead of the envelope
+    lfo_page: bool,
+    /// The console's LFO fields, empty ones taking the driver's default
+    lfo_inputs: Vec<String>,
     result: String,
 }
 
-/// Console(Putting In?)
-#[derive(Clone, Copy, Debug, PartialEq)]
-pub enum Mode {
-    NDS(bool),
-    GBA(bool),
-}
-
 #[derive(Clone, Debug)]
 pub enum Message {
     CalculatePressed,
     ModeSwitchPressed,
+    EngineSwitchPressed,
     AttackChanged(String),
     DecayChanged(String),
     SustainChanged(String),
     ReleaseChanged(String),
+    HoldChanged(String),
+    ParameterChanged(usize, String),
+    PortPressed,
+    MatrixPressed,
+    SfzPressed,
+    ProgramChanged(String),
+    MidiPressed,
+    FilePathChanged(String),
+    EntryChanged(String),
+    ExportPressed,
+    LoadPressed,
+    PagePressed,
+    LfoChanged(usize, String),
+    LfoPressed,
     CopyToClipboard,
     PasteFromClipboard(i32),
 }
 
 impl App {
-    fn calculate_attack(&self, mode: Mode) -> f64 {
-        let mut steps = 0;
-        let mut vel = ZERO_POINT;
-        let mut vol = self.attack as u16;
-        match mode {
-            Mode::NDS(false) => {
-                if self.attack != 0 {
-                    while vel < 0 {
-                        steps += 1;
-                        vel = self.attack_table[self.attack as usize] * vel / 0xff;
-                    }
-                    steps as f64 / TPS_NDS
-                } else {
-                    INFINITY
-                }
-            }
-            Mode::NDS(true) => {
-                for i in 0..127_usize {
-                    if i != 0 {
-                        while vel < 0 {
-                            steps += 1;
-                            vel = self.attack_table[i] * vel / 0xff;
-                        }
-                        if (steps as f64 / TPS_NDS) < self.attack_f as f64 {
-                            return i as f64;
-                        }
-                    }
-                    steps = 0;
-                    vel = ZERO_POINT;
-                }
-                127.0
-            }
-            Mode::GBA(false) => {
-                if self.attack != 0 {
-                    while vol < 255 {
-                        steps += 1;
-                        vol += self.attack as u16
-                    }
-                    steps as f64 / TPS_GBA
-                } else {
-                    INFINITY
-                }
-            }
-            Mode::GBA(true) => {
-                for i in 0..255_u16 {
-                    if i != 0 {
-                        vol = i;
-                        while vol < 255 {
-                            steps += 1;
-                            vol += i
-                        }
-                        if (steps as f64 / TPS_GBA) < self.attack_f as f64 {
-                            return i as f64;
-                        }
-                        steps = 0;
-                    }
-                }
-                255.0
-            }
-        }
+    fn model(&self) -> &dyn EnvelopeModel {
+        self.models[self.model].as_ref()
     }
 
-    fn calculate_decay(&self, mode: Mode) -> f64 {
-        let mut steps = 0;
-        let mut vel = 0;
-        let mut vol = 255;
-        match mode {
-            Mode::NDS(false) => {
-                while vel > ZERO_POINT {
-                    steps += 1;
-                    vel -= self.decay_table[self.decay as usize];
-                }
-                steps as f64 / TPS_NDS
-            }
-            Mode::NDS(true) => {
-                for i in 0..127_u8 {
-                    if i != 0 {
-                        while vel > ZERO_POINT {
-                            steps += 1;
-                            vel -= self.decay_table[i as usize];
-                        }
-                        if (steps as f64 / TPS_NDS) < self.decay_f as f64 {
-                            return i as f64;
-                        }
-                    }
-                    steps = 0;
-                    vel = 0;
-                }
-                127.0
-            }
-            Mode::GBA(false) => {
-                if self.decay == 0 {
-                    0.0
-                } else if self.decay != 255 {
-                    while vol > 0 {
-                        steps += 1;
-                        vol = (vol * self.decay as u16) >> 8 // "divide by 255"
-                    }
-                    steps as f64 / TPS_GBA
-                } else {
-                    INFINITY
-                }
-            }
-            Mode::GBA(true) => {
-                for i in 0..255_u16 {
-                    if i != 255 {
-                        while vol > 0 {
-                            steps += 1;
-                            vol = (vol * i) >> 8
-                        }
-                        if (steps as f64 / TPS_GBA) > self.decay_f as f64 {
-                            return i as f64;
-                        }
-                        steps = 0;
-                        vol = 255;
-                    }
-                }
-                1.0
-            }
+    fn values(&self) -> Values {
+        Values {
+            attack: self.attack,
+            hold: self.hold,
+            decay: self.decay,
+            sustain: self.sustain,
+            release: self.release,
+            parameters: self.parameters.clone(),
         }
     }
 
-    fn calculate_sustain(&self, mode: Mode) -> f64 {
-        let zero_point = ZERO_POINT as f64;
-        match mode {
-            Mode::NDS(false) => {
-                if self.sustain == 0 {
-                    127.0
-                } else {
-                    let sus = self.sustain_table[(127 - self.sustain) as usize] as f64;
-                    let amplitude = sus / zero_point; // 0 is 1.0, 127 is 0.0
-                    let decibels = (20.0 * f64::log10(amplitude.abs())) / 2.0; // For some reason having a less prominent sustain difference tends to sound more accurate
-                    decibels.abs() // Written as "decibels to diminish by" in Polyphone
-                }
-            }
-            Mode::NDS(true) => {
-                for i in 0..127_u8 {
-                    if i != 127 {
-                        let sus = self.sustain_table[(127 - i) as usize] as f64;
-                        let amplitude = sus / zero_point;
-                        let decibels = 20.0 * f64::log10(amplitude.abs());
-                        if f64::from(-self.sustain_f) < decibels {
-                            return i as f64;
-                        }
-                    }
-                }
-                127.0
-            }
-            Mode::GBA(false) => {
-                if self.sustain == 0 {
-                    0.0
-                } else {
-                    let amplitude = self.sustain as f64 / 255.0; // 255 is 1.0, 0 is 0.0
-                    let decibels = 20.0 * f64::log10(amplitude);
-                    decibels.abs()
-                }
-            }
-            Mode::GBA(true) => {
-                for i in 0..255_u16 {
-                    if i != 255 {
-                        let amplitude = i as f64 / 255.0;
-                        let decibels = 20.0 * f64::log10(amplitude);
-                        if f64::from(-self.sustain_f) < decibels {
-                            return i as f64;
-                        }
-                    }
-                }
-                255.0
-            }
+    fn times(&self) -> Times {
+        Times {
+            attack: self.attack_f as f64,
+            hold: self.hold_f as f64,
+            decay: self.decay_f as f64,
+            sustain: self.sustain_f as f64,
+            release: self.release_f as f64,
         }
     }
 
-    fn calculate_release(&self, mode: Mode) -> f64 {
-        let mut steps = 0;
-        let mut vel = 0;
-        let mut vol = 255;
-        match mode {
-            Mode::NDS(false) => {
-                while vel > ZERO_POINT {
-                    steps += 1;
-                    vel -= self.decay_table[self.release as usize]
-                }
-                steps as f64 / TPS_NDS
-            }
-            Mode::NDS(true) => {
-                for i in 0..127_u8 {
-                    if i != 0 {
-                        while vel > ZERO_POINT {
-                            steps += 1;
-                            vel -= self.decay_table[i as usize];
-                        }
-                        if (steps as f64 / TPS_NDS) < self.release_f as f64 {
-                            return i as f64;
-                        }
-                    }
-                    steps = 0;
-                    vel = 0;
-                }
-                127.0
-            }
-            Mode::GBA(false) => {
-                if self.release == 0 {
-                    0.0
-                } else if self.release != 255 {
-                    while vol > 0 {
-                        steps += 1;
-                        vol = (vol * self.release as u16) >> 8
-                    }
-                    steps as f64 / TPS_GBA
-                } else {
-                    INFINITY
-                }
-            }
-            Mode::GBA(true) => {
-                for i in 0..255_u16 {
-                    if i != 255 {
-                        while vol > 0 {
-                            steps += 1;
-                            vol = (vol * i as u16) >> 8
-                        }
-                        if (steps as f64 / TPS_GBA) > self.release_f as f64 {
-                            return i as f64;
-                        }
-                        steps = 0;
-                        vol = 255;
-                    }
-                }
-                1.0
-            }
+    /// The last "To SDAT"-style result
+    fn result_values(&self) -> Values {
+        Values {
+            attack: self.attack_result as u8,
+            hold: self.hold_result as u8,
+            decay: self.decay_result as u8,
+            sustain: self.sustain_result as u8,
+            release: self.release_result as u8,
+            parameters: self.parameters.clone(),
         }
     }
 
-    fn calculate(&mut self, mode: Mode) {
-        self.attack_result = self.calculate_attack(mode);
-        self.decay_result = self.calculate_decay(mode);
-        self.sustain_result = self.calculate_sustain(mode);
-        self.release_result = self.calculate_release(mode);
-        let result_string = match mode {
-            Mode::NDS(false) => format!(
-                "Attack: {:.3} \nDecay: {:.3} \nSustain: {:.3} \nRelease: {:.3}",
-                self.attack_result, self.decay_result, self.sustain_result, self.release_result
-            ),
-            Mode::NDS(true) => format!(
-                "Attack: {:.0} \nDecay: {:.0} \nSustain: {:.0} \nRelease: {:.0}",
-                self.attack_result, self.decay_result, self.sustain_result, self.release_result
-            ),
-            Mode::GBA(false) => format!(
-                "Attack: {:.3} \nDecay: {:.3} \nSustain: {:.3} \nRelease: {:.3}",
-                self.attack_result, self.decay_result, self.sustain_result, self.release_result
-            ),
-            Mode::GBA(true) => format!(
-                "Attack: {:.0} \nDecay: {:.0} \nSustain: {:.0} \nRelease: {:.0}",
-                self.attack_result, self.decay_result, self.sustain_result, self.release_result
-            ),
+    fn calculate(&mut self) {
+        let model = self.model();
+        let results = if self.putting_in {
+            let values = model.to_values(&self.times(), &self.parameters);
+            Times {
+                attack: values.attack as f64,
+                hold: values.hold as f64,
+                decay: values.decay as f64,
+                sustain: values.sustain as f64,
+                release: values.release as f64,
+            }
+        } else {
+            model.to_times(&self.values())
+        };
+        let extra_results = if self.putting_in {
+            Vec::new()
+        } else {
+            model.extra_results(&self.values())
         };
+        let has_hold = model.has_hold();
+        self.attack_result = results.attack;
+        self.hold_result = results.hold;
+        self.decay_result = results.decay;
+        self.sustain_result = results.sustain;
+        self.release_result = results.release;
+        self.extra_results = extra_results;
+        let precision = if self.putting_in { 0 } else { 3 };
+        let mut result_string = format!("Attack: {:.*} \n", precision, self.attack_result);
+        if has_hold {
+            result_string += &format!("Hold: {:.*} \n", precision, self.hold_result);
+        }
+        result_string += &format!(
+            "Decay: {:.*} \nSustain: {:.*} \nRelease: {:.*}",
+            precision,
+            self.decay_result,
+            precision,
+            self.sustain_result,
+            precision,
+            self.release_result
+        );
+        for (name, result) in &self.extra_results {
+            result_string += &format!(" \n{}: {:.3}", name, result);
+        }
         self.result = result_string
     }
 
     fn calculate_button_text(&self) -> String {
-        match self.mode {
-            Mode::NDS(true) => "To SDAT".to_string(),
-            Mode::GBA(true) => "To Sappy".to_string(),
-            Mode::NDS(false) | Mode::GBA(false) => "To SF2".to_string(),
+        if self.putting_in {
+            format!("To {}", self.model().format_name())
+        } else {
+            "To SF2".to_string()
         }
     }
 
+    /// First model of the next console
+    fn next_console(&self) -> usize {
+        let console = self.model().console();
+        (1..self.models.len())
+            .map(|i| (self.model + i) % self.models.len())
+            .find(|&i| self.models[i].console() != console)
+            .unwrap_or(self.model)
+    }
+
+    /// Next model for the same console
+    fn next_engine(&self) -> usize {
+        let console = self.model().console();
+        (1..self.models.len())
+            .map(|i| (self.model + i) % self.models.len())
+            .find(|&i| self.models[i].console() == console)
+            .unwrap_or(self.model)
+    }
+
     fn mode_switch_button_text(&self) -> String {
-        match self.mode {
-            Mode::NDS(false) | Mode::NDS(true) => "Switch to GBA".to_string(),
-            Mode::GBA(false) | Mode::GBA(true) => "Switch to NDS".to_string(),
+        format!("Switch to {}", self.models[self.next_console()].console())
+    }
+
+    fn engine_switch_button_text(&self) -> String {
+        format!("Engine: {}", self.model().name())
+    }
+
+    /// NDS envelopes port to MP2K and GBA ones back to NDS
+    fn port_target(&self) -> Option<usize> {
+        let target = match self.model().console() {
+            "NDS" => "gba",
+            "GBA" => "nds",
+            _ => return None,
+        };
+        self.models.iter().position(|model| model.id() == target)
+    }
+
+    fn port(&mut self, target: usize) {
+        let (from, to) = (self.model(), self.models[target].as_ref());
+        let values = self.values();
+        let port = port::port(from, to, &values);
+        self.result = port::describe(from, to, &values, &port);
+        self.ported = Some((target, port.values));
+        self.copy_result = false;
+    }
+
+    fn show_matrix(&mut self) {
+        self.result = port::matrix(&self.models, self.model, &self.values());
+        self.ported = None;
+        self.copy_result = true;
+    }
+
+    fn show_sfz(&mut self) {
+        let times = self.model().to_times(&self.values());
+        self.result = export::sfz::opcodes(&times);
+        self.ported = None;
+        self.copy_result = true;
+    }
+
+    fn show_midi(&mut self) {
+        let times = self.model().to_times(&self.values());
+        self.result = export::midi::describe(&times, self.program, 0);
+        self.ported = None;
+        self.copy_result = true;
+    }
+
+    /// The modulation page's fields as SF2 LFO generators
+    fn lfo(&self) -> Result<String, String> {
+        let driver = lfo::driver(self.model().console())
+            .ok_or(format!("No LFO converter for {}", self.model().console()))?;
+        let values = driver
+            .fields
+            .iter()
+            .zip(&self.lfo_inputs)
+            .map(|((name, default), input)| match input.trim() {
+                "" => Ok(*default),
+                input => input
+                    .parse()
+                    .map_err(|_| format!("{} \"{}\" isn't a whole number", name, input)),
+            })
+            .collect::<Result<Vec<u16>, String>>()?;
+        let ticks_per_second = self
+            .models
+            .iter()
+            .find(|model| model.id() == driver.model)
+            .map_or(self.model().ticks_per_second(), |model| {
+                model.ticks_per_second()
+            });
+        Ok(lfo::describe(&driver.convert(&values, ticks_per_second)))
+    }
+
+    fn export(&self) -> Result<String, String> {
+        let path = std::path::Path::new(&self.file_path);
+        let sample = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or("instrument".to_string());
+        let instrument = export::Instrument {
+            sample,
+            values: self.values(),
+        };
+        export::save(self.model(), &[instrument], path, self.entry, None)?;
+        Ok(format!("Saved {}", self.file_path))
+    }
+
+    /// Reads a Furnace instrument into the fields of the model for its chip
+    fn load(&mut self) -> Result<String, String> {
+        let fui = std::fs::read(&self.file_path).map_err(|e| e.to_string())?;
+        let (id, times) = export::furnace::read(&fui)?;
+        let index = self
+            .models
+            .iter()
+            .position(|model| model.id() == id)
+            .ok_or("The instrument's console isn't available")?;
+        self.select_model(index);
+        let values = self.model().to_values(&times, &self.parameters);
+        self.update(Message::AttackChanged(values.attack.to_string()));
+        self.update(Message::DecayChanged(values.decay.to_string()));
+        self.update(Message::SustainChanged(values.sustain.to_string()));
+        self.update(Message::ReleaseChanged(values.release.to_string()));
+        self.update(Message::CalculatePressed);
+        Ok(format!("Loaded {} \n{}", self.file_path, self.result))
+    }
+
+    fn select_model(&mut self, model: usize) {
+        let lfo_model = |app: &Self| lfo::driver(app.model().console()).map(|driver| driver.model);
+        let previous_lfo = lfo_model(self);
+        self.model = model;
+        self.ported = None;
+        self.copy_result = false;
+        let count = self.model().parameters().len();
+        self.parameters = vec![0; count];
+        self.parameter_inputs = vec!["".to_string(); count];
+        // Another driver's fields mean something else
+        if lfo_model(self) != previous_lfo {
+            let fields =
+                lfo::driver(self.model().console()).map_or(0, |driver| driver.fields.len());
+            self.lfo_inputs = vec!["".to_string(); fields];
         }
+        self.refresh_fields();
     }
 
     fn refresh_fields(&mut self) {
@@ -342,17 +363,44 @@ impl App {
         self.update(Message::DecayChanged(self.decay_input.clone()));
         self.update(Message::SustainChanged(self.sustain_input.clone()));
         self.update(Message::ReleaseChanged(self.release_input.clone()));
+        if self.model().has_hold() {
+            self.update(Message::HoldChanged(self.hold_input.clone()));
+        }
+    }
+
+    /// The modulation page: the console's LFO fields converted to SF2's LFO generators
+    fn lfo_view(&self) -> Element<'_, Message> {
+        let mut fields = row!();
+        if let Some(driver) = lfo::driver(self.model().console()) {
+            for (i, (name, default)) in driver.fields.iter().enumerate() {
+                fields = fields.push(column!(
+                    text_input(&default.to_string(), &self.lfo_inputs[i])
+                        .on_input(move |s| Message::LfoChanged(i, s))
+                        .on_submit(Message::LfoPressed),
+                    text(*name),
+                ));
+            }
+        }
+        column!(
+            fields,
+            button(text("To SF2"))
+                .on_press(Message::LfoPressed)
+                .padding(Padding::from([10, 20])),
+            text(self.result.to_string()),
+            row!(
+                button(text(self.mode_switch_button_text())).on_press(Message::ModeSwitchPressed),
+                button(text("Envelope")).on_press(Message::PagePressed),
+            ),
+            button(text("Copy to clipboard".to_string())).on_press(Message::CopyToClipboard),
+        )
+        .into()
     }
 
-    fn string_is_int(&self, s: String) -> bool {
+    fn string_is_int(&self, s: String, max: u8) -> bool {
         let x_int_result = s.parse::<u16>();
         let x_float_result = s.parse::<f32>();
         if let Ok(_x) = x_int_result {
-            if self.mode == Mode::NDS(true) && _x > 127 {
-                false
-            } else {
-                true
-            }
+            _x <= max as u16
         } else if let Ok(_x) = x_float_result {
             false
         } else {
@@ -366,45 +414,10 @@ impl Sandbox for App {
 
     fn new() -> Self {
         Self {
-            mode: Mode::NDS(false),
+            models: model::models(),
+            model: 0,
+            putting_in: false,
             clipboard: None,
-            attack_table: [
-                255, 254, 253, 252, 251, 250, 249, 248, 247, 246, 245, 244, 243, 242, 241, 240,
-                239, 238, 237, 236, 235, 234, 233, 232, 231, 230, 229, 228, 227, 226, 225, 224,
-                223, 222, 221, 220, 219, 218, 217, 216, 215, 214, 213, 212, 211, 210, 209, 208,
-                207, 206, 205, 204, 203, 202, 201, 200, 199, 198, 197, 196, 195, 194, 193, 192,
-                191, 190, 189, 188, 187, 186, 185, 184, 183, 182, 181, 180, 179, 178, 177, 176,
-                175, 174, 173, 172, 171, 170, 169, 168, 167, 166, 165, 164, 163, 162, 161, 160,
-                159, 158, 157, 156, 155, 154, 153, 152, 151, 150, 149, 148, 147, 143, 137, 132,
-                127, 123, 116, 109, 100, 92, 84, 73, 63, 51, 38, 26, 14, 5, 1, 0,
-            ]
-            .to_vec(),
-            decay_table: [
-                1, 3, 5, 7, 9, 11, 13, 15, 17, 19, 21, 23, 25, 27, 29, 31, 33, 35, 37, 39, 41, 43,
-                45, 47, 49, 51, 53, 55, 57, 59, 61, 63, 65, 67, 69, 71, 73, 75, 77, 79, 81, 83, 85,
-                87, 89, 91, 93, 95, 97, 99, 101, 102, 104, 105, 107, 108, 110, 111, 113, 115, 116,
-                118, 120, 122, 124, 126, 128, 130, 132, 135, 137, 140, 142, 145, 148, 151, 154,
-                157, 160, 163, 167, 171, 175, 179, 183, 187, 192, 197, 202, 208, 213, 219, 226,
-                233, 240, 248, 256, 265, 274, 284, 295, 307, 320, 334, 349, 366, 384, 404, 427,
-                452, 480, 512, 549, 591, 640, 698, 768, 853, 960, 1097, 1280, 1536, 1920, 2560,
-                3840, 7680, 15360, 65535,
-            ]
-            .to_vec(),
-            sustain_table: [
-                -92544, -92416, -92288, -83328, -76928, -71936, -67840, -64384, -61440, -58880,
-                -56576, -54400, -52480, -50688, -49024, -47488, -46080, -44672, -43392, -42240,
-                -41088, -40064, -39040, -38016, -36992, -36096, -35328, -34432, -33664, -32896,
-                -32128, -31360, -30592, -29952, -29312, -28672, -28032, -27392, -26880, -26240,
-                -25728, -25088, -24576, -24064, -23552, -23040, -22528, -22144, -21632, -21120,
-                -20736, -20224, -19840, -19456, -19072, -18560, -18176, -17792, -17408, -17024,
-                -16640, -16256, -16000, -15616, -15232, -14848, -14592, -14208, -13952, -13568,
-                -13184, -12928, -12672, -12288, -12032, -11648, -11392, -11136, -10880, -10496,
-                -10240, -9984, -9728, -9472, -9216, -8960, -8704, -8448, -8192, -7936, -7680,
-                -7424, -7168, -6912, -6656, -6400, -6272, -6016, -5760, -5504, -5376, -5120, -4864,
-                -4608, -4480, -4224, -3968, -3840, -3584, -3456, -3200, -2944, -2816, -2560, -2432,
-                -2176, -2048, -1792, -1664, -1408, -1280, -1024, -896, -768, -512, -384, -128, 0,
-            ]
-            .to_vec(),
             attack: 127,
             attack_f: 0.0,
             attack_input: "".to_string(),
@@ -421,6 +434,22 @@ impl Sandbox for App {
             release_f: 0.0,
             release_input: "".to_string(),
             release_result: 0.0,
+            hold: 0,
+            hold_f: 0.0,
+            hold_input: "".to_string(),
+            hold_result: 0.0,
+            parameters: Vec::new(),
+            parameter_inputs: Vec::new(),
+            extra_results: Vec::new(),
+            ported: None,
+            copy_result: false,
+            file_path: "".to_string(),
+            program: 0,
+            program_input: "".to_string(),
+            entry: 0,
+            entry_input: "".to_string(),
+            lfo_page: false,
+            lfo_inputs: vec!["".to_string(); lfo::driver("NDS").map_or(0, |d| d.fields.len())],
             result: "".to_string(),
         }
     }
@@ -437,46 +466,119 @@ impl Sandbox for App {
         "NDS ADSR Calculator".to_string()
     }
 
-    fn view(&self) -> Element<Message> {
-        column!(
-            row!(
-                column!(
-                    text_input("127", &self.attack_input.to_string())
-                        .on_input(Message::AttackChanged)
-                        .on_submit(Message::CalculatePressed)
-                        .on_paste(|_| Message::PasteFromClipboard(0)),
-                    text("Attack"),
-                ),
-                column!(
-                    text_input("127", &self.decay_input.to_string())
-                        .on_input(Message::DecayChanged)
-                        .on_submit(Message::CalculatePressed)
-                        .on_paste(|_| Message::PasteFromClipboard(1)),
-                    text("Decay"),
-                ),
-                column!(
-                    text_input("127", &self.sustain_input.to_string())
-                        .on_input(Message::SustainChanged)
-                        .on_submit(Message::CalculatePressed)
-                        .on_paste(|_| Message::PasteFromClipboard(2)),
-                    text("Sustain"),
-                ),
-                column!(
-                    text_input("127", &self.release_input.to_string())
-                        .on_input(Message::ReleaseChanged)
-                        .on_submit(Message::CalculatePressed)
-                        .on_paste(|_| Message::PasteFromClipboard(3)),
-                    text("Release"),
-                ),
+    fn view(&self) -> Element<'_, Message> {
+        if self.lfo_page {
+            return self.lfo_view();
+        }
+        let mut fields = row!(
+            column!(
+                text_input("127", &self.attack_input.to_string())
+                    .on_input(Message::AttackChanged)
+                    .on_submit(Message::CalculatePressed)
+                    .on_paste(|_| Message::PasteFromClipboard(0)),
+                text("Attack"),
             ),
-            button(text(self.calculate_button_text()))
-                .on_press(Message::CalculatePressed)
-                .padding(Padding::from([10, 20])),
+            column!(
+                text_input("127", &self.decay_input.to_string())
+                    .on_input(Message::DecayChanged)
+                    .on_submit(Message::CalculatePressed)
+                    .on_paste(|_| Message::PasteFromClipboard(1)),
+                text("Decay"),
+            ),
+            column!(
+                text_input("127", &self.sustain_input.to_string())
+                    .on_input(Message::SustainChanged)
+                    .on_submit(Message::CalculatePressed)
+                    .on_paste(|_| Message::PasteFromClipboard(2)),
+                text("Sustain"),
+            ),
+            column!(
+                text_input("127", &self.release_input.to_string())
+                    .on_input(Message::ReleaseChanged)
+                    .on_submit(Message::CalculatePressed)
+                    .on_paste(|_| Message::PasteFromClipboard(3)),
+                text("Release"),
+            ),
+        );
+        if self.model().has_hold() {
+            fields = fields.push(column!(
+                text_input("0", &self.hold_input.to_string())
+                    .on_input(Message::HoldChanged)
+                    .on_submit(Message::CalculatePressed),
+                text("Hold"),
+            ));
+        }
+        for (i, parameter) in self.model().parameters().iter().enumerate() {
+            fields = fields.push(column!(
+                text_input("0", &self.parameter_inputs[i].to_string())
+                    .on_input(move |s| Message::ParameterChanged(i, s))
+                    .on_submit(Message::CalculatePressed),
+                text(parameter.name),
+            ));
+        }
+        let mut switches =
+            row!(button(text(self.mode_switch_button_text())).on_press(Message::ModeSwitchPressed));
+        if self.next_engine() != self.model {
+            switches = switches.push(
+                button(text(self.engine_switch_button_text()))
+                    .on_press(Message::EngineSwitchPressed),
+            );
+        }
+        if let (false, Some(target)) = (self.putting_in, self.port_target()) {
+            switches = switches.push(
+                button(text(format!("Port to {}", self.models[target].console())))
+                    .on_press(Message::PortPressed),
+            );
+        }
+        if !self.putting_in {
+            switches = switches.push(button(text("All consoles")).on_press(Message::MatrixPressed));
+        }
+        switches = switches.push(button(text("Modulation")).on_press(Message::PagePressed));
+        let mut calculate = row!(button(text(self.calculate_button_text()))
+            .on_press(Message::CalculatePressed)
+            .padding(Padding::from([10, 20])));
+        if !self.putting_in {
+            calculate = calculate.push(
+                button(text("To SFZ"))
+                    .on_press(Message::SfzPressed)
+                    .padding(Padding::from([10, 20])),
+            );
+            calculate = calculate.push(column!(
+                text_input("1", &self.program_input)
+                    .on_input(Message::ProgramChanged)
+                    .on_submit(Message::MidiPressed),
+                text("GM program"),
+            ));
+            calculate = calculate.push(
+                button(text("To MIDI"))
+                    .on_press(Message::MidiPressed)
+                    .padding(Padding::from([10, 20])),
+            );
+        }
+        let mut export = row!(text_input("instrument.sfz", &self.file_path)
+            .on_input(Message::FilePathChanged)
+            .on_submit(Message::ExportPressed));
+        if self.file_path.to_lowercase().ends_with(".xml") {
+            export = export.push(column!(
+                text_input("0", &self.entry_input)
+                    .on_input(Message::EntryChanged)
+                    .on_submit(Message::ExportPressed),
+                text("Envelope"),
+            ));
+        }
+        if !self.putting_in {
+            export = export.push(button(text("Save")).on_press(Message::ExportPressed));
+        }
+        export = export.push(button(text("Load")).on_press(Message::LoadPressed));
+        column!(
+            fields,
+            calculate,
             text(self.result.to_string()),
-            button(text(self.mode_switch_button_text())).on_press(Message::ModeSwitchPressed),
+            switches,
             button(text("Copy to clipboard".to_string())).on_press(Message::CopyToClipboard),
             button(text("Paste from clipboard".to_string()))
                 .on_press(Message::PasteFromClipboard(0)),
+            export,
         )
         .into()
     }
@@ -486,113 +588,146 @@ impl Sandbox for App {
             Message::CalculatePressed => {
                 if !self.attack == 0 || !self.decay == 0 || !self.sustain == 0 || !self.release == 0
                 {
-                    if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                        self.mode = Mode::NDS(false)
-                    } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                        self.mode = Mode::GBA(false)
-                    }
+                    self.putting_in = false
                 }
-                self.calculate(self.mode.clone())
+                self.ported = None;
+                self.copy_result = false;
+                self.calculate()
             }
-            Message::ModeSwitchPressed => {
-                match self.mode {
-                    Mode::NDS(false) => self.mode = Mode::GBA(false),
-                    Mode::NDS(true) => self.mode = Mode::GBA(true),
-                    Mode::GBA(false) | Mode::GBA(true) => self.mode = Mode::NDS(true),
-                };
-                self.refresh_fields();
+            Message::PortPressed => {
+                if let Some(target) = self.port_target() {
+                    self.port(target)
+                }
+            }
+            Message::MatrixPressed => self.show_matrix(),
+            Message::SfzPressed => self.show_sfz(),
+            Message::ProgramChanged(s) => {
+                self.program = s.parse::<u8>().unwrap_or(1).clamp(1, 128) - 1;
+                self.program_input = s
+            }
+            Message::MidiPressed => self.show_midi(),
+            Message::FilePathChanged(s) => self.file_path = s,
+            Message::EntryChanged(s) => {
+                self.entry = s.parse().unwrap_or(0);
+                self.entry_input = s
+            }
+            Message::ExportPressed => {
+                if !self.putting_in {
+                    self.result = self.export().unwrap_or_else(|e| e);
+                    self.ported = None;
+                    self.copy_result = false;
+                }
+            }
+            Message::LoadPressed => {
+                self.result = self.load().unwrap_or_else(|e| e);
+            }
+            Message::PagePressed => {
+                self.lfo_page = !self.lfo_page;
+                self.result = "".to_string();
+                self.ported = None;
+                self.copy_result = false;
+            }
+            Message::LfoChanged(i, s) => self.lfo_inputs[i] = s,
+            Message::LfoPressed => {
+                self.ported = None;
+                let lfo = self.lfo();
+                self.copy_result = lfo.is_ok();
+                self.result = lfo.unwrap_or_else(|e| e);
+            }
+            Message::ModeSwitchPressed => self.select_model(self.next_console()),
+            Message::EngineSwitchPressed => {
+                self.select_model(self.next_engine());
+                // The fields mean the same on every engine, so the result is redone for this one
+                if !self.result.is_empty() && !self.lfo_page {
+                    self.calculate()
+                }
             }
             Message::AttackChanged(s) => {
-                if self.string_is_int(s.clone()) {
+                if self.string_is_int(s.clone(), self.model().max_rate()) {
                     self.attack_input = s.clone();
                     self.attack_f = s.clone().parse().unwrap();
                     self.attack = s.parse().unwrap()
                 } else {
                     self.attack_input = s.clone();
-                    if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                        self.mode = Mode::NDS(true)
-                    } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                        self.mode = Mode::GBA(true)
-                    }
+                    self.putting_in = true;
                     self.attack = 0;
                     self.attack_f = s.parse().unwrap_or(0.0)
                 }
             }
             Message::DecayChanged(s) => {
-                if self.string_is_int(s.clone()) {
+                if self.string_is_int(s.clone(), self.model().max_rate()) {
                     self.decay_input = s.clone();
                     self.decay_f = s.clone().parse().unwrap();
                     self.decay = s.parse().unwrap()
                 } else {
                     self.decay_input = s.clone();
-                    if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                        self.mode = Mode::NDS(true)
-                    } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                        self.mode = Mode::GBA(true)
-                    }
+                    self.putting_in = true;
                     self.decay = 0;
                     self.decay_f = s.parse().unwrap_or(0.0)
                 }
             }
             Message::SustainChanged(s) => {
-                if self.string_is_int(s.clone()) {
+                if self.string_is_int(s.clone(), self.model().max_sustain()) {
                     self.sustain_input = s.clone();
                     self.sustain_f = s.clone().parse().unwrap();
                     self.sustain = s.parse().unwrap()
                 } else {
                     self.sustain_input = s.clone();
-                    if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                        self.mode = Mode::NDS(true)
-                    } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                        self.mode = Mode::GBA(true)
-                    }
+                    self.putting_in = true;
                     self.sustain = 0;
                     self.sustain_f = s.parse().unwrap_or(0.0)
                 }
             }
             Message::ReleaseChanged(s) => {
-                if self.string_is_int(s.clone()) {
+                if self.string_is_int(s.clone(), self.model().max_release()) {
                     self.release_input = s.clone();
                     self.release_f = s.clone().parse().unwrap();
                     self.release = s.parse().unwrap()
                 } else {
                     self.release_input = s.clone();
-                    if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                        self.mode = Mode::NDS(true)
-                    } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                        self.mode = Mode::GBA(true)
-                    }
+                    self.putting_in = true;
                     self.release = 0;
                     self.release_f = s.parse().unwrap_or(0.0)
                 }
             }
+            Message::HoldChanged(s) => {
+                if self.string_is_int(s.clone(), self.model().max_rate()) {
+                    self.hold_input = s.clone();
+                    self.hold_f = s.clone().parse().unwrap();
+                    self.hold = s.parse().unwrap()
+                } else {
+                    self.hold_input = s.clone();
+                    self.putting_in = true;
+                    self.hold = 0;
+                    self.hold_f = s.parse().unwrap_or(0.0)
+                }
+            }
+            Message::ParameterChanged(i, s) => {
+                let max = self.model().parameters()[i].max;
+                self.parameters[i] = s.parse::<u8>().unwrap_or(0).min(max);
+                self.parameter_inputs[i] = s
+            }
             Message::CopyToClipboard => {
-                let content = if self.mode == Mode::NDS(false) || self.mode == Mode::GBA(false) {
-                    format!(
-                        "{:.3}\n\n{:.3}\n{:.3}\n{:.3}",
-                        self.attack_result,
-                        self.decay_result,
-                        self.sustain_result,
-                        self.release_result
-                    )
-                } else if self.mode == Mode::NDS(true) {
+                let content = if self.copy_result {
+                    self.result.clone()
+                } else if let Some((target, values)) = &self.ported {
+                    self.models[*target].format_values(values)
+                } else if !self.putting_in {
+                    let hold = if self.model().has_hold() {
+                        format!("{:.3}", self.hold_result)
+                    } else {
+                        "".to_string()
+                    };
                     format!(
-                        "{:.0}\t{:.0}\t{:.0}\t{:.0}",
-                        self.attack_result,
-                        self.decay_result,
-                        self.sustain_result,
-                        self.release_result
-                    )
-                } else if self.mode == Mode::GBA(true) {
-                    format!(
-                        "{:.0}, {:.0}, {:.0}, {:.0}",
+                        "{:.3}\n{}\n{:.3}\n{:.3}\n{:.3}",
                         self.attack_result,
+                        hold,
                         self.decay_result,
                         self.sustain_result,
                         self.release_result
                     )
                 } else {
-                    "".to_string()
+                    self.model().format_values(&self.result_values())
                 };
                 self.clipboard = Some(ClipboardProvider::new().unwrap());
                 if let Some(ref mut cb) = self.clipboard {
@@ -619,13 +754,8 @@ impl Sandbox for App {
                                     encountered_first_exclamation = true;
                                     continue;
                                 }
-                            } else if s.parse::<u8>().is_ok() {
-                                if s.parse::<u8>().unwrap() > 127
-                                && (self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false)) {
-                                    num = (s.parse::<u8>().unwrap() >> 1).to_string();
-                                } else {
-                                    num = s.parse::<u8>().unwrap().to_string();
-                                }
+                            } else if let Ok(value) = s.parse::<u8>() {
+                                num = self.model().scale_pasted(value).to_string();
                             } else if s.parse::<f32>().is_ok() {
                                 num = s.parse::<f32>().unwrap().to_string();
                             }
@@ -654,16 +784,14 @@ impl Sandbox for App {
                 }
             }
         }
-        if self.string_is_int(self.attack_input.clone())
-            && self.string_is_int(self.decay_input.clone())
-            && self.string_is_int(self.sustain_input.clone())
-            && self.string_is_int(self.release_input.clone())
+        if self.string_is_int(self.attack_input.clone(), self.model().max_rate())
+            && self.string_is_int(self.decay_input.clone(), self.model().max_rate())
+            && self.string_is_int(self.sustain_input.clone(), self.model().max_sustain())
+            && self.string_is_int(self.release_input.clone(), self.model().max_release())
+            && (!self.model().has_hold()
+                || self.string_is_int(self.hold_input.clone(), self.model().max_rate()))
         {
-            if self.mode == Mode::NDS(true) || self.mode == Mode::NDS(false) {
-                self.mode = Mode::NDS(false)
-            } else if self.mode == Mode::GBA(true) || self.mode == Mode::GBA(false) {
-                self.mode = Mode::GBA(false)
-            }
+            self.putting_in = false
         }
     }
 }
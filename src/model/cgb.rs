@@ -0,0 +1,93 @@
+//! MP2K's envelopes for the Game Boy (CGB) channels: square waves, wave and noise
+//!
+//! The engine steps the channel volume between 0 and 15 itself, one step every `value` frames.
+//! Attack, decay and release only go up to 7 and sustain up to 15.
+
+use super::mp2k::TPS_GBA;
+use super::{EnvelopeModel, Times, Values};
+
+const MAX_VOLUME: u8 = 15;
+const MAX_RATE: u8 = 7;
+
+pub struct Cgb;
+
+/// Seconds to move the volume across its whole range, which is what SF2 times measure
+fn sweep_time(rate: u8) -> f64 {
+    (rate.min(MAX_RATE) as f64 * MAX_VOLUME as f64) / TPS_GBA
+}
+
+fn find_rate(seconds: f64) -> u8 {
+    (seconds * TPS_GBA / MAX_VOLUME as f64)
+        .round()
+        .clamp(0.0, MAX_RATE as f64) as u8
+}
+
+impl EnvelopeModel for Cgb {
+    fn id(&self) -> &str {
+        "cgb"
+    }
+
+    fn console(&self) -> &str {
+        "GBA"
+    }
+
+    fn name(&self) -> &str {
+        "MP2K CGB"
+    }
+
+    fn format_name(&self) -> &str {
+        "Sappy"
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        TPS_GBA
+    }
+
+    fn max_value(&self) -> u8 {
+        MAX_VOLUME
+    }
+
+    fn max_rate(&self) -> u8 {
+        MAX_RATE
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        sweep_time(values.attack)
+    }
+
+    /// A decay of 0 drops straight to the sustain volume
+    fn decay_time(&self, values: &Values) -> f64 {
+        sweep_time(values.decay)
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        if values.sustain == 0 {
+            144.0 // Lowest sustain SF2 allows
+        } else {
+            let amplitude = values.sustain.min(MAX_VOLUME) as f64 / MAX_VOLUME as f64;
+            (20.0 * f64::log10(amplitude)).abs()
+        }
+    }
+
+    /// A release of 0 cuts the note off at key off
+    fn release_time(&self, values: &Values) -> f64 {
+        sweep_time(values.release)
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        find_rate(times.attack)
+    }
+
+    fn find_decay(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        find_rate(times.decay)
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        let amplitude = 10_f64.powf(-times.sustain.abs() / 20.0);
+        (amplitude * MAX_VOLUME as f64).round() as u8
+    }
+
+    fn find_release(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        find_rate(times.release)
+    }
+}
@@ -0,0 +1,286 @@
+//! Envelope models for every supported console and sound engine
+//!
+//! Each model converts the values a console stores into SF2-style times (seconds, and decibels
+//! of attenuation for the sustain) and searches for the values that come closest to given times.
+//! To add a console, implement [`EnvelopeModel`] in a new module and list it in [`models`], or
+//! describe it in a file in the `consoles` folder (see [`table`] and [`script`]).
+
+use std::{fs, path::PathBuf};
+
+mod cgb;
+mod mp2k;
+mod point_envelope;
+mod script;
+mod sdat;
+mod simulator;
+mod table;
+mod ym2612;
+
+pub use sdat::{nds_attenuation, nds_pan, NDS};
+
+/// An envelope as the console stores it
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Values {
+    pub attack: u8,
+    pub hold: u8,
+    pub decay: u8,
+    pub sustain: u8,
+    pub release: u8,
+    /// Model-specific settings, in the order of [`EnvelopeModel::parameters`]
+    pub parameters: Vec<u8>,
+}
+
+/// An envelope in SF2 units
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Times {
+    pub attack: f64,
+    pub hold: f64,
+    pub decay: f64,
+    /// Decibels to diminish by
+    pub sustain: f64,
+    pub release: f64,
+}
+
+/// An envelope's volume tick by tick, from 0.0 for silence to 1.0 for full volume
+pub struct Curve {
+    pub ticks_per_second: f64,
+    /// From note on until the sustain is reached
+    pub held: Vec<f64>,
+    /// From key off until silence
+    pub released: Vec<f64>,
+}
+
+/// Curves are cut off after this long
+const MAX_CURVE_SECONDS: f64 = 600.0;
+
+impl Curve {
+    fn max_ticks(ticks_per_second: f64) -> usize {
+        (MAX_CURVE_SECONDS * ticks_per_second) as usize
+    }
+
+    /// SF2's curves: a linear attack, then decay and release falling evenly in decibels
+    pub fn from_times(times: &Times, ticks_per_second: f64) -> Curve {
+        let max_ticks = Curve::max_ticks(ticks_per_second);
+        let ticks = |seconds: f64| ((seconds * ticks_per_second).round() as usize).min(max_ticks);
+        let amplitude = |decibels: f64| 10_f64.powf(-decibels / 20.0);
+        let sustain = if times.decay.is_finite() {
+            times.sustain.abs()
+        } else {
+            0.0
+        };
+
+        let attack = ticks(times.attack);
+        // An attack that never ends stays silent
+        if !times.attack.is_finite() {
+            return Curve {
+                ticks_per_second,
+                held: vec![0.0; max_ticks],
+                released: vec![0.0],
+            };
+        }
+        let mut held: Vec<f64> = (0..attack).map(|i| i as f64 / attack as f64).collect();
+        held.extend(std::iter::repeat_n(1.0, ticks(times.hold)));
+        let decay_step = 144.0 / (times.decay * ticks_per_second);
+        let mut level = 0.0;
+        while level < sustain && held.len() < max_ticks {
+            held.push(amplitude(level));
+            level += decay_step;
+        }
+        held.push(amplitude(sustain));
+
+        let release_step = 144.0 / (times.release * ticks_per_second);
+        let mut released = Vec::new();
+        let mut level = sustain;
+        while level < 144.0 && released.len() < max_ticks {
+            released.push(amplitude(level));
+            level += release_step;
+        }
+        released.push(0.0);
+        Curve {
+            ticks_per_second,
+            held,
+            released,
+        }
+    }
+}
+
+/// A setting that isn't one of the envelope stages but changes how they behave
+pub struct Parameter {
+    pub name: &'static str,
+    pub max: u8,
+}
+
+pub trait EnvelopeModel {
+    /// Short lowercase name used on the command line
+    fn id(&self) -> &str;
+
+    /// Consoles with more than one model get an engine switch in the GUI
+    fn console(&self) -> &str;
+
+    /// Shown on the engine switch, the console name if there's only one engine
+    fn name(&self) -> &str;
+
+    /// What the calculate button converts to, e.g. "SDAT"
+    fn format_name(&self) -> &str;
+
+    fn ticks_per_second(&self) -> f64;
+
+    /// Highest value the console accepts in any field
+    fn max_value(&self) -> u8;
+
+    /// Highest attack, hold, decay and release, for consoles where it's below the sustain's
+    fn max_rate(&self) -> u8 {
+        self.max_value()
+    }
+
+    /// Highest sustain, for consoles where it's below the other fields'
+    fn max_sustain(&self) -> u8 {
+        self.max_value()
+    }
+
+    /// Highest release, for consoles where it's below the other rates'
+    fn max_release(&self) -> u8 {
+        self.max_rate()
+    }
+
+    fn has_hold(&self) -> bool {
+        false
+    }
+
+    fn parameters(&self) -> &'static [Parameter] {
+        &[]
+    }
+
+    fn attack_time(&self, values: &Values) -> f64;
+
+    fn hold_time(&self, _values: &Values) -> f64 {
+        0.0
+    }
+
+    fn decay_time(&self, values: &Values) -> f64;
+
+    fn sustain_level(&self, values: &Values) -> f64;
+
+    fn release_time(&self, values: &Values) -> f64;
+
+    fn find_attack(&self, times: &Times, parameters: &[u8]) -> u8;
+
+    fn find_hold(&self, _times: &Times, _parameters: &[u8]) -> u8 {
+        0
+    }
+
+    fn find_decay(&self, times: &Times, parameters: &[u8]) -> u8;
+
+    fn find_sustain(&self, times: &Times, parameters: &[u8]) -> u8;
+
+    fn find_release(&self, times: &Times, parameters: &[u8]) -> u8;
+
+    /// Results that don't fit in an SF2 envelope but are worth showing
+    fn extra_results(&self, _values: &Values) -> Vec<(&'static str, f64)> {
+        Vec::new()
+    }
+
+    /// The envelope's shape, for formats made of points. Models that know their console's curve
+    /// draw it instead of SF2's.
+    fn curve(&self, values: &Values) -> Curve {
+        Curve::from_times(&self.to_times(values), self.ticks_per_second())
+    }
+
+    /// How the console's own tools expect values to be pasted
+    fn format_values(&self, values: &Values) -> String {
+        format!(
+            "{}, {}, {}, {}",
+            values.attack, values.decay, values.sustain, values.release
+        )
+    }
+
+    /// Values pasted in from a format with a different range
+    fn scale_pasted(&self, value: u8) -> u8 {
+        value
+    }
+
+    fn to_times(&self, values: &Values) -> Times {
+        Times {
+            attack: self.attack_time(values),
+            hold: self.hold_time(values),
+            decay: self.decay_time(values),
+            sustain: self.sustain_level(values),
+            release: self.release_time(values),
+        }
+    }
+
+    fn to_values(&self, times: &Times, parameters: &[u8]) -> Values {
+        Values {
+            attack: self.find_attack(times, parameters),
+            hold: self.find_hold(times, parameters),
+            decay: self.find_decay(times, parameters),
+            sustain: self.find_sustain(times, parameters),
+            release: self.find_release(times, parameters),
+            parameters: parameters.to_vec(),
+        }
+    }
+}
+
+/// Every `.toml`, `.json` and `.rhai` file in a `consoles` folder next to the executable or in
+/// the working directory
+fn console_files() -> Vec<PathBuf> {
+    let mut folders = Vec::new();
+    if let Some(folder) = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|parent| parent.join("consoles")))
+    {
+        folders.push(folder);
+    }
+    if let Ok(folder) = std::env::current_dir().map(|dir| dir.join("consoles")) {
+        if !folders.contains(&folder) {
+            folders.push(folder);
+        }
+    }
+
+    let mut paths = Vec::new();
+    for folder in folders {
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        let mut files: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+        files.sort();
+        paths.extend(files.into_iter().filter(|path| {
+            path.extension()
+                .is_some_and(|e| e == "toml" || e == "json" || e == "rhai")
+        }));
+    }
+    paths
+}
+
+/// Every model, grouped by console in the order the GUI cycles through them, followed by the
+/// ones loaded from the `consoles` folder
+pub fn models() -> Vec<Box<dyn EnvelopeModel>> {
+    let mut models: Vec<Box<dyn EnvelopeModel>> = vec![
+        Box::new(sdat::NDS),
+        Box::new(mp2k::Mp2k),
+        Box::new(cgb::Cgb),
+        Box::new(point_envelope::KRAWALL),
+        Box::new(point_envelope::GAX),
+        Box::new(sdat::RBNK),
+        Box::new(sdat::CBNK),
+        Box::new(ym2612::Ym2612),
+    ];
+    for path in console_files() {
+        let model: Result<Box<dyn EnvelopeModel>, String> =
+            if path.extension().is_some_and(|e| e == "rhai") {
+                script::ScriptModel::load(&path).map(|model| Box::new(model) as _)
+            } else {
+                table::TableModel::load(&path).map(|model| Box::new(model) as _)
+            };
+        match model {
+            Ok(model) if models.iter().any(|m| m.id() == model.id()) => eprintln!(
+                "Skipping {}: a model called \"{}\" already exists",
+                path.display(),
+                model.id()
+            ),
+            Ok(model) => models.push(model),
+            Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+        }
+    }
+    models
+}
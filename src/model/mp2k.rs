@@ -0,0 +1,128 @@
+//! MP2K (Sappy), the sound engine most GBA games use
+
+use super::{EnvelopeModel, Times, Values};
+
+/// The engine updates once per frame
+pub const TPS_GBA: f64 = 59.7275;
+
+pub struct Mp2k;
+
+fn attack_steps(attack: u16) -> u32 {
+    let mut steps = 0;
+    let mut vol = attack;
+    while vol < 255 {
+        steps += 1;
+        vol += attack
+    }
+    steps
+}
+
+/// Decay and release multiply the volume every frame
+fn fall_steps(rate: u16) -> u32 {
+    let mut steps = 0;
+    let mut vol = 255;
+    while vol > 0 {
+        steps += 1;
+        vol = (vol * rate) >> 8 // "divide by 255"
+    }
+    steps
+}
+
+fn fall_time(rate: u8) -> f64 {
+    if rate == 0 {
+        0.0
+    } else if rate != 255 {
+        fall_steps(rate as u16) as f64 / TPS_GBA
+    } else {
+        f64::INFINITY
+    }
+}
+
+fn find_fall(seconds: f64) -> u8 {
+    for i in 0..255_u8 {
+        if (fall_steps(i as u16) as f64 / TPS_GBA) > seconds {
+            return i;
+        }
+    }
+    1
+}
+
+impl EnvelopeModel for Mp2k {
+    fn id(&self) -> &str {
+        "gba"
+    }
+
+    fn console(&self) -> &str {
+        "GBA"
+    }
+
+    fn name(&self) -> &str {
+        "MP2K"
+    }
+
+    fn format_name(&self) -> &str {
+        "Sappy"
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        TPS_GBA
+    }
+
+    fn max_value(&self) -> u8 {
+        255
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        if values.attack != 0 {
+            attack_steps(values.attack as u16) as f64 / TPS_GBA
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn decay_time(&self, values: &Values) -> f64 {
+        fall_time(values.decay)
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        if values.sustain == 0 {
+            0.0
+        } else {
+            let amplitude = values.sustain as f64 / 255.0; // 255 is 1.0, 0 is 0.0
+            let decibels = 20.0 * f64::log10(amplitude);
+            decibels.abs()
+        }
+    }
+
+    fn release_time(&self, values: &Values) -> f64 {
+        fall_time(values.release)
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        for i in 1..255_u8 {
+            if (attack_steps(i as u16) as f64 / TPS_GBA) < times.attack {
+                return i;
+            }
+        }
+        255
+    }
+
+    fn find_decay(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        find_fall(times.decay)
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        for i in 0..255_u8 {
+            let amplitude = i as f64 / 255.0;
+            let decibels = 20.0 * f64::log10(amplitude);
+            if -times.sustain < decibels {
+                return i;
+            }
+        }
+        255
+    }
+
+    fn find_release(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        find_fall(times.release)
+    }
+}
@@ -0,0 +1,120 @@
+//! Tracker-style envelopes made of points, as used by Krawall (XM/S3M) and GAX
+//!
+//! The four values are read as the envelope (0, 0) -> (attack, peak) -> (attack + decay, sustain),
+//! held at the sustain point, then -> (release, 0) after key off. Volume moves linearly between
+//! points once per tick.
+
+use super::mp2k::TPS_GBA;
+use super::{EnvelopeModel, Times, Values};
+
+pub struct PointEnvelope {
+    id: &'static str,
+    name: &'static str,
+    ticks_per_second: f64,
+    /// Volume of the envelope's loudest point
+    peak: u8,
+}
+
+pub const KRAWALL: PointEnvelope = PointEnvelope {
+    id: "krawall",
+    name: "Krawall",
+    ticks_per_second: 125.0 * 2.0 / 5.0, // XM ticks at the default 125 BPM
+    peak: 64,
+};
+
+/// Games call GAX's `GAX_play()` from their VBlank handler, so the envelope moves once a frame,
+/// and its instrument envelopes keep each point's volume in a byte
+pub const GAX: PointEnvelope = PointEnvelope {
+    id: "gax",
+    name: "GAX",
+    ticks_per_second: TPS_GBA,
+    peak: 255,
+};
+
+impl PointEnvelope {
+    fn seconds(&self, ticks: f64) -> f64 {
+        ticks / self.ticks_per_second
+    }
+
+    fn ticks(&self, seconds: f64) -> u8 {
+        (seconds * self.ticks_per_second).round().clamp(0.0, 255.0) as u8
+    }
+}
+
+impl EnvelopeModel for PointEnvelope {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn console(&self) -> &str {
+        "GBA"
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn format_name(&self) -> &str {
+        self.name
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        self.ticks_per_second
+    }
+
+    fn max_value(&self) -> u8 {
+        255
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        self.seconds(values.attack as f64)
+    }
+
+    /// SF2 decay times are for the full range, so stretch the slope down to silence
+    fn decay_time(&self, values: &Values) -> f64 {
+        let sustain = values.sustain.min(self.peak);
+        if sustain == self.peak {
+            0.0
+        } else {
+            self.seconds(values.decay as f64 * self.peak as f64 / (self.peak - sustain) as f64)
+        }
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        if values.sustain == 0 {
+            144.0 // Lowest sustain SF2 allows
+        } else {
+            let amplitude = values.sustain.min(self.peak) as f64 / self.peak as f64;
+            (20.0 * f64::log10(amplitude)).abs()
+        }
+    }
+
+    /// Release starts from the sustain point, so stretch it like the decay
+    fn release_time(&self, values: &Values) -> f64 {
+        let sustain = values.sustain.min(self.peak);
+        if sustain == 0 {
+            0.0
+        } else {
+            self.seconds(values.release as f64 * self.peak as f64 / sustain as f64)
+        }
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.ticks(times.attack)
+    }
+
+    fn find_decay(&self, times: &Times, parameters: &[u8]) -> u8 {
+        let sustain = self.find_sustain(times, parameters);
+        self.ticks(times.decay * (self.peak - sustain) as f64 / self.peak as f64)
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        let amplitude = 10_f64.powf(-times.sustain.abs() / 20.0);
+        (amplitude * self.peak as f64).round() as u8
+    }
+
+    fn find_release(&self, times: &Times, parameters: &[u8]) -> u8 {
+        let sustain = self.find_sustain(times, parameters);
+        self.ticks(times.release * sustain as f64 / self.peak as f64)
+    }
+}
@@ -0,0 +1,230 @@
+//! Engines described by a Rhai script, for testing ideas about envelope code that doesn't fit a
+//! table
+//!
+//! The script describes the engine in `info()` and gives the level after one tick of each stage.
+//! The attack runs from `silence` up to `peak` and the decay and release run back down, so those
+//! two levels are the stage thresholds:
+//!
+//! ```rhai
+//! fn info() {
+//!     #{
+//!         id: "homebrew",
+//!         console: "GBA",
+//!         name: "Homebrew",
+//!         format_name: "Homebrew",
+//!         ticks_per_second: 59.7275,
+//!         max_value: 255,
+//!         peak: 255,
+//!         silence: 0,
+//!     }
+//! }
+//!
+//! fn attack(level, value) { level + value }
+//! fn decay(level, value) { level * value / 256 }
+//! fn release(level, value) { level * value / 256 }
+//! fn sustain(value) { value }
+//! ```
+//!
+//! `release` defaults to `decay`, and `sustain` to a straight line from silence to peak. Every
+//! value is run through the script once when it's loaded, so converting doesn't call it again.
+
+use std::path::Path;
+
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use super::simulator::Simulator;
+use super::{EnvelopeModel, Times, Values};
+
+/// Keeps a runaway script from hanging the app
+const MAX_OPERATIONS: u64 = 100_000;
+
+pub struct ScriptModel {
+    id: String,
+    console: String,
+    name: String,
+    format_name: String,
+    simulator: Simulator,
+    /// Seconds or decibels for every value, by stage
+    attack: Vec<f64>,
+    decay: Vec<f64>,
+    sustain: Vec<f64>,
+    release: Vec<f64>,
+}
+
+/// The compiled script, only kept while its results are worked out
+struct Script {
+    engine: Engine,
+    ast: AST,
+    simulator: Simulator,
+    has_release: bool,
+    has_sustain: bool,
+}
+
+fn get<T: Clone + 'static>(info: &Map, key: &str) -> Result<T, String> {
+    info.get(key)
+        .and_then(|value| value.clone().try_cast::<T>())
+        .ok_or_else(|| format!("info() is missing \"{}\" or it has the wrong type", key))
+}
+
+impl ScriptModel {
+    pub fn load(path: &Path) -> Result<ScriptModel, String> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine
+            .compile_file(path.into())
+            .map_err(|e| e.to_string())?;
+        let has_function = |name: &str, params: usize| {
+            ast.iter_functions()
+                .any(|f| f.name == name && f.params.len() == params)
+        };
+        for (name, params) in [("info", 0), ("attack", 2), ("decay", 2)] {
+            if !has_function(name, params) {
+                return Err(format!("the script needs a {} function", name));
+            }
+        }
+        let has_release = has_function("release", 2);
+        let has_sustain = has_function("sustain", 1);
+
+        let info: Map = engine
+            .call_fn(&mut Scope::new(), &ast, "info", ())
+            .map_err(|e| e.to_string())?;
+        let name: Option<String> = get(&info, "name").ok();
+        let console: String = get(&info, "console")?;
+        let max_value: i64 = get(&info, "max_value")?;
+        let simulator = Simulator {
+            ticks_per_second: get::<f64>(&info, "ticks_per_second")
+                .or_else(|_| get::<i64>(&info, "ticks_per_second").map(|tps| tps as f64))?,
+            max_value: u8::try_from(max_value).map_err(|_| "max_value must be 0-255")?,
+            peak: get(&info, "peak")?,
+            silence: get(&info, "silence")?,
+            immediate_attack: get(&info, "immediate_attack").unwrap_or(false),
+        };
+        simulator.check()?;
+        let script = Script {
+            engine,
+            ast,
+            simulator: simulator.clone(),
+            has_release,
+            has_sustain,
+        };
+        let all = |result: &dyn Fn(u8) -> f64| (0..=simulator.max_value).map(result).collect();
+        Ok(ScriptModel {
+            id: get(&info, "id")?,
+            name: name.unwrap_or(console.clone()),
+            console,
+            format_name: get(&info, "format_name")?,
+            attack: all(&|i| script.attack_seconds(i)),
+            decay: all(&|i| script.fall_seconds("decay", i)),
+            sustain: all(&|i| script.sustain_decibels(i)),
+            release: all(&|i| script.fall_seconds(script.release_function(), i)),
+            simulator,
+        })
+    }
+
+    fn lookup(&self, results: &[f64], value: u8) -> f64 {
+        results[value.min(self.simulator.max_value) as usize]
+    }
+
+    fn first_past(&self, results: &[f64], target: f64) -> u8 {
+        self.simulator
+            .first_past(target, |i| self.lookup(results, i))
+    }
+}
+
+impl Script {
+    /// Runs one of the script's functions, None if it fails or doesn't return an integer
+    fn call(&self, function: &str, args: impl rhai::FuncArgs) -> Option<i64> {
+        self.engine
+            .call_fn::<Dynamic>(&mut Scope::new(), &self.ast, function, args)
+            .ok()
+            .and_then(|result| result.as_int().ok())
+    }
+
+    fn attack_seconds(&self, value: u8) -> f64 {
+        let value = value.min(self.simulator.max_value) as i64;
+        self.simulator
+            .attack_time(|level| self.call("attack", (level, value)))
+    }
+
+    fn fall_seconds(&self, function: &str, value: u8) -> f64 {
+        let value = value.min(self.simulator.max_value) as i64;
+        self.simulator
+            .fall_time(|level| self.call(function, (level, value)))
+    }
+
+    fn release_function(&self) -> &'static str {
+        if self.has_release {
+            "release"
+        } else {
+            "decay"
+        }
+    }
+
+    fn sustain_decibels(&self, value: u8) -> f64 {
+        let level = if self.has_sustain {
+            self.call("sustain", (value as i64,))
+                .unwrap_or(self.simulator.silence)
+        } else {
+            self.simulator.linear_level(value)
+        };
+        self.simulator.decibels(level)
+    }
+}
+
+impl EnvelopeModel for ScriptModel {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn console(&self) -> &str {
+        &self.console
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn format_name(&self) -> &str {
+        &self.format_name
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        self.simulator.ticks_per_second
+    }
+
+    fn max_value(&self) -> u8 {
+        self.simulator.max_value
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        self.lookup(&self.attack, values.attack)
+    }
+
+    fn decay_time(&self, values: &Values) -> f64 {
+        self.lookup(&self.decay, values.decay)
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        self.lookup(&self.sustain, values.sustain)
+    }
+
+    fn release_time(&self, values: &Values) -> f64 {
+        self.lookup(&self.release, values.release)
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.first_past(&self.attack, times.attack)
+    }
+
+    fn find_decay(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.first_past(&self.decay, times.decay)
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.first_past(&self.sustain, times.sustain)
+    }
+
+    fn find_release(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.first_past(&self.release, times.release)
+    }
+}
@@ -0,0 +1,302 @@
+//! SDAT-style envelopes: NDS, and the Wii and 3DS NintendoWare banks that kept its tables
+
+use super::{Curve, EnvelopeModel, Times, Values};
+
+/// Envelope level when the note is silent; 0 is full volume
+pub const ZERO_POINT: i32 = -92544;
+
+pub const ATTACK_TABLE: [i32; 128] = [
+    255, 254, 253, 252, 251, 250, 249, 248, 247, 246, 245, 244, 243, 242, 241, 240, 239, 238, 237,
+    236, 235, 234, 233, 232, 231, 230, 229, 228, 227, 226, 225, 224, 223, 222, 221, 220, 219, 218,
+    217, 216, 215, 214, 213, 212, 211, 210, 209, 208, 207, 206, 205, 204, 203, 202, 201, 200, 199,
+    198, 197, 196, 195, 194, 193, 192, 191, 190, 189, 188, 187, 186, 185, 184, 183, 182, 181, 180,
+    179, 178, 177, 176, 175, 174, 173, 172, 171, 170, 169, 168, 167, 166, 165, 164, 163, 162, 161,
+    160, 159, 158, 157, 156, 155, 154, 153, 152, 151, 150, 149, 148, 147, 143, 137, 132, 127, 123,
+    116, 109, 100, 92, 84, 73, 63, 51, 38, 26, 14, 5, 1, 0,
+];
+
+pub const DECAY_TABLE: [i32; 128] = [
+    1, 3, 5, 7, 9, 11, 13, 15, 17, 19, 21, 23, 25, 27, 29, 31, 33, 35, 37, 39, 41, 43, 45, 47, 49,
+    51, 53, 55, 57, 59, 61, 63, 65, 67, 69, 71, 73, 75, 77, 79, 81, 83, 85, 87, 89, 91, 93, 95, 97,
+    99, 101, 102, 104, 105, 107, 108, 110, 111, 113, 115, 116, 118, 120, 122, 124, 126, 128, 130,
+    132, 135, 137, 140, 142, 145, 148, 151, 154, 157, 160, 163, 167, 171, 175, 179, 183, 187, 192,
+    197, 202, 208, 213, 219, 226, 233, 240, 248, 256, 265, 274, 284, 295, 307, 320, 334, 349, 366,
+    384, 404, 427, 452, 480, 512, 549, 591, 640, 698, 768, 853, 960, 1097, 1280, 1536, 1920, 2560,
+    3840, 7680, 15360, 65535,
+];
+
+pub const SUSTAIN_TABLE: [i32; 128] = [
+    -92544, -92416, -92288, -83328, -76928, -71936, -67840, -64384, -61440, -58880, -56576, -54400,
+    -52480, -50688, -49024, -47488, -46080, -44672, -43392, -42240, -41088, -40064, -39040, -38016,
+    -36992, -36096, -35328, -34432, -33664, -32896, -32128, -31360, -30592, -29952, -29312, -28672,
+    -28032, -27392, -26880, -26240, -25728, -25088, -24576, -24064, -23552, -23040, -22528, -22144,
+    -21632, -21120, -20736, -20224, -19840, -19456, -19072, -18560, -18176, -17792, -17408, -17024,
+    -16640, -16256, -16000, -15616, -15232, -14848, -14592, -14208, -13952, -13568, -13184, -12928,
+    -12672, -12288, -12032, -11648, -11392, -11136, -10880, -10496, -10240, -9984, -9728, -9472,
+    -9216, -8960, -8704, -8448, -8192, -7936, -7680, -7424, -7168, -6912, -6656, -6400, -6272,
+    -6016, -5760, -5504, -5376, -5120, -4864, -4608, -4480, -4224, -3968, -3840, -3584, -3456,
+    -3200, -2944, -2816, -2560, -2432, -2176, -2048, -1792, -1664, -1408, -1280, -1024, -896, -768,
+    -512, -384, -128, 0,
+];
+
+pub struct Sdat {
+    id: &'static str,
+    console: &'static str,
+    format_name: &'static str,
+    ticks_per_second: f64,
+    /// NintendoWare banks add a hold stage between attack and decay
+    hold: bool,
+}
+
+pub const NDS: Sdat = Sdat {
+    id: "nds",
+    console: "NDS",
+    format_name: "SDAT",
+    ticks_per_second: 192.0,
+    hold: false,
+};
+
+/// Wii NintendoWare bank (.brbnk)
+pub const RBNK: Sdat = Sdat {
+    id: "rbnk",
+    console: "Wii",
+    format_name: "RBNK",
+    ticks_per_second: 1000.0 / 3.0, // AX audio frames are 3ms
+    hold: true,
+};
+
+/// 3DS NintendoWare bank (.bcbnk)
+pub const CBNK: Sdat = Sdat {
+    id: "cbnk",
+    console: "3DS",
+    format_name: "CBNK",
+    ticks_per_second: 32728.0 / 160.0, // 160 samples per DSP frame
+    hold: true,
+};
+
+fn attack_steps(attack: u8) -> u32 {
+    let mut steps = 0;
+    let mut vel = ZERO_POINT;
+    while vel < 0 {
+        steps += 1;
+        vel = ATTACK_TABLE[attack as usize] * vel / 0xff;
+    }
+    steps
+}
+
+/// Decay and release both fall to the zero point using the decay table
+fn fall_steps(rate: u8) -> u32 {
+    let mut steps = 0;
+    let mut vel = 0;
+    while vel > ZERO_POINT {
+        steps += 1;
+        vel -= DECAY_TABLE[rate as usize];
+    }
+    steps
+}
+
+/// NintendoWare holds for (hold + 1)^2 / 4 milliseconds
+fn hold_seconds(hold: u8) -> f64 {
+    let hold = hold as u32;
+    ((hold + 1) * (hold + 1) / 4) as f64 / 1000.0
+}
+
+/// SF2 initial attenuation in centibels for an NDS volume (0-127), which the sound driver turns
+/// into decibels with the sustain table
+pub fn nds_attenuation(volume: u8) -> f64 {
+    SUSTAIN_TABLE[volume.min(127) as usize].unsigned_abs() as f64 / 128.0
+}
+
+/// SF2 pan, -500 for left to 500 for right, for an NDS pan from 0 to 127 with 64 in the middle
+pub fn nds_pan(pan: u8) -> i16 {
+    ((pan.min(127) as i16 - 64) * 500 / 64).max(-500)
+}
+
+/// Envelope levels are tenths of a decibel, times 128
+fn amplitude(level: i32) -> f64 {
+    if level <= ZERO_POINT {
+        0.0
+    } else {
+        10_f64.powf(level as f64 / 128.0 / 10.0 / 20.0)
+    }
+}
+
+impl Sdat {
+    fn seconds(&self, steps: u32) -> f64 {
+        steps as f64 / self.ticks_per_second
+    }
+
+    fn find_fall(&self, seconds: f64) -> u8 {
+        for i in 1..127_u8 {
+            if self.seconds(fall_steps(i)) < seconds {
+                return i;
+            }
+        }
+        127
+    }
+}
+
+impl EnvelopeModel for Sdat {
+    fn id(&self) -> &str {
+        self.id
+    }
+
+    fn console(&self) -> &str {
+        self.console
+    }
+
+    fn name(&self) -> &str {
+        self.console
+    }
+
+    fn format_name(&self) -> &str {
+        self.format_name
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        self.ticks_per_second
+    }
+
+    fn max_value(&self) -> u8 {
+        127
+    }
+
+    fn has_hold(&self) -> bool {
+        self.hold
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        if values.attack != 0 {
+            self.seconds(attack_steps(values.attack.min(127)))
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    fn hold_time(&self, values: &Values) -> f64 {
+        if self.hold {
+            hold_seconds(values.hold.min(127))
+        } else {
+            0.0
+        }
+    }
+
+    fn decay_time(&self, values: &Values) -> f64 {
+        self.seconds(fall_steps(values.decay.min(127)))
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        if values.sustain == 0 {
+            127.0
+        } else {
+            let sus = SUSTAIN_TABLE[(127 - values.sustain.min(127)) as usize] as f64;
+            let amplitude = sus / ZERO_POINT as f64; // 0 is 1.0, 127 is 0.0
+            let decibels = (20.0 * f64::log10(amplitude.abs())) / 2.0; // For some reason having a less prominent sustain difference tends to sound more accurate
+            decibels.abs() // Written as "decibels to diminish by" in Polyphone
+        }
+    }
+
+    fn release_time(&self, values: &Values) -> f64 {
+        self.seconds(fall_steps(values.release.min(127)))
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        for i in 1..127_u8 {
+            if self.seconds(attack_steps(i)) < times.attack {
+                return i;
+            }
+        }
+        127
+    }
+
+    fn find_hold(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        if !self.hold {
+            return 0;
+        }
+        // Longest hold below the time, as the other stages round
+        (0..=127_u8)
+            .rev()
+            .find(|&i| hold_seconds(i) < times.hold)
+            .unwrap_or(0)
+    }
+
+    fn find_decay(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.find_fall(times.decay)
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        for i in 0..127_u8 {
+            let sus = SUSTAIN_TABLE[(127 - i) as usize] as f64;
+            let amplitude = sus / ZERO_POINT as f64;
+            let decibels = 20.0 * f64::log10(amplitude.abs());
+            if -times.sustain < decibels {
+                return i;
+            }
+        }
+        127
+    }
+
+    fn find_release(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.find_fall(times.release)
+    }
+
+    /// The multiplicative attack and the decay and release stepping down through the decay table
+    fn curve(&self, values: &Values) -> Curve {
+        let max_ticks = Curve::max_ticks(self.ticks_per_second);
+        let attack = ATTACK_TABLE[values.attack.min(127) as usize];
+        let sustain = SUSTAIN_TABLE[values.sustain.min(127) as usize];
+        let mut held = Vec::new();
+        let mut level = ZERO_POINT;
+        while level < 0 && held.len() < max_ticks {
+            held.push(amplitude(level));
+            level = attack * level / 0xff;
+        }
+        let hold = (self.hold_time(values) * self.ticks_per_second).round() as usize;
+        held.extend(std::iter::repeat_n(1.0, hold));
+        let decay = DECAY_TABLE[values.decay.min(127) as usize];
+        let mut level = 0;
+        while level > sustain && held.len() < max_ticks {
+            held.push(amplitude(level));
+            level -= decay;
+        }
+        held.push(amplitude(sustain));
+
+        let release = DECAY_TABLE[values.release.min(127) as usize];
+        let mut released = Vec::new();
+        let mut level = sustain;
+        while level > ZERO_POINT && released.len() < max_ticks {
+            released.push(amplitude(level));
+            level -= release;
+        }
+        released.push(0.0);
+        Curve {
+            ticks_per_second: self.ticks_per_second,
+            held,
+            released,
+        }
+    }
+
+    fn format_values(&self, values: &Values) -> String {
+        if self.hold {
+            // NintendoWare editors list the envelope as attack, decay, sustain, hold, release
+            format!(
+                "{}\t{}\t{}\t{}\t{}",
+                values.attack, values.decay, values.sustain, values.hold, values.release
+            )
+        } else {
+            format!(
+                "{}\t{}\t{}\t{}",
+                values.attack, values.decay, values.sustain, values.release
+            )
+        }
+    }
+
+    /// 8-bit values, like the ones in a voicegroup, are halved
+    fn scale_pasted(&self, value: u8) -> u8 {
+        if value > 127 {
+            value >> 1
+        } else {
+            value
+        }
+    }
+}
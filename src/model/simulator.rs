@@ -0,0 +1,116 @@
+//! Tick-by-tick envelope simulation shared by the table-driven and scripted models
+//!
+//! The level starts at `silence` for the attack and at `peak` for the decay and release, and the
+//! stage is over once it reaches the other end.
+
+use serde::Deserialize;
+
+use super::Curve;
+
+/// Keeps `peak` and `silence` far enough from the ends of an `i64` to subtract one from the other
+pub const MAX_LEVEL: i64 = i32::MAX as i64;
+
+/// Lowest sustain SF2 allows
+const MAX_ATTENUATION: f64 = 144.0;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Simulator {
+    pub ticks_per_second: f64,
+    pub max_value: u8,
+    /// Level at full volume
+    pub peak: i64,
+    /// Level when the note is silent
+    pub silence: i64,
+    /// The attack's first tick happens as the note starts, as MP2K sets the level straight to
+    /// the attack value
+    #[serde(default)]
+    pub immediate_attack: bool,
+}
+
+impl Simulator {
+    pub fn check(&self) -> Result<(), String> {
+        if self.ticks_per_second <= 0.0 {
+            return Err("ticks_per_second must be above 0".to_string());
+        }
+        if self.peak.abs() > MAX_LEVEL || self.silence.abs() > MAX_LEVEL {
+            return Err(format!("peak and silence must be within ±{}", MAX_LEVEL));
+        }
+        if self.peak == self.silence {
+            return Err("peak and silence can't be the same level".to_string());
+        }
+        Ok(())
+    }
+
+    /// Seconds to move from one level to another, infinite if `tick` never gets there. With
+    /// `immediate` the first tick takes no time.
+    fn time(
+        &self,
+        from: i64,
+        to: i64,
+        immediate: bool,
+        mut tick: impl FnMut(i64) -> Option<i64>,
+    ) -> f64 {
+        // Stages longer than a curve can show never finish
+        let max_steps = Curve::max_ticks(self.ticks_per_second);
+        let direction = from.cmp(&to);
+        let mut level = from;
+        if immediate {
+            match tick(level) {
+                Some(next) => level = next,
+                None => return f64::INFINITY,
+            }
+        }
+        let mut steps = 0;
+        while level.cmp(&to) == direction {
+            if steps == max_steps {
+                return f64::INFINITY;
+            }
+            steps += 1;
+            match tick(level) {
+                Some(next) if next != level => level = next,
+                _ => return f64::INFINITY,
+            }
+        }
+        steps as f64 / self.ticks_per_second
+    }
+
+    pub fn attack_time(&self, tick: impl FnMut(i64) -> Option<i64>) -> f64 {
+        self.time(self.silence, self.peak, self.immediate_attack, tick)
+    }
+
+    /// Decay and release both fall from the peak to silence
+    pub fn fall_time(&self, tick: impl FnMut(i64) -> Option<i64>) -> f64 {
+        self.time(self.peak, self.silence, false, tick)
+    }
+
+    /// Level of a sustain value when there's no table for it
+    pub fn linear_level(&self, value: u8) -> i64 {
+        let fraction = value.min(self.max_value) as f64 / self.max_value as f64;
+        self.silence + ((self.peak - self.silence) as f64 * fraction) as i64
+    }
+
+    /// Decibels to diminish by to get from the peak to a level
+    pub fn decibels(&self, level: i64) -> f64 {
+        let amplitude = (level - self.silence) as f64 / (self.peak - self.silence) as f64;
+        if amplitude <= 0.0 {
+            MAX_ATTENUATION
+        } else {
+            (20.0 * f64::log10(amplitude)).abs().min(MAX_ATTENUATION)
+        }
+    }
+
+    /// First value whose result gets past the target, going the way the results run, which is
+    /// how the built-in models round
+    pub fn first_past(&self, target: f64, result: impl Fn(u8) -> f64) -> u8 {
+        let falling = result(self.max_value) < result(0);
+        (0..=self.max_value)
+            .find(|&i| {
+                if falling {
+                    result(i) < target
+                } else {
+                    result(i) > target
+                }
+            })
+            .unwrap_or(self.max_value)
+    }
+}
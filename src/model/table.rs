@@ -0,0 +1,257 @@
+//! Table-driven models loaded from TOML or JSON files, for engine variants that don't need code
+//!
+//! A definition names the console, its tick rate and value range, the envelope level at full
+//! volume and at silence, and how each stage moves the level every tick:
+//!
+//! ```toml
+//! id = "mp2k2x"
+//! console = "GBA"
+//! name = "MP2K 2x"
+//! format_name = "Sappy"
+//! ticks_per_second = 119.455
+//! max_value = 255
+//! peak = 255
+//! silence = 0
+//!
+//! [attack]
+//! rule = "additive"
+//!
+//! [decay]
+//! rule = "multiplicative"
+//! divisor = 256
+//! instant = [0]
+//! endless = [255]
+//! ```
+//!
+//! Each stage's `table` maps a value to the amount the rule uses; without one the value is used
+//! directly. `instant` and `endless` list values the engine special-cases to end the stage at
+//! once or never, and `immediate_attack` applies the attack's first tick as the note starts.
+//! Levels, table amounts and divisors are limited to 32 bits. `release` defaults to the decay
+//! stage and the top-level `sustain` table gives the level for each sustain value, or a straight
+//! line from silence to peak if it's left out.
+
+use std::{fs, path::Path};
+
+use serde::Deserialize;
+
+use super::simulator::{Simulator, MAX_LEVEL};
+use super::{EnvelopeModel, Times, Values};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rule {
+    /// level = level * amount / divisor
+    Multiplicative,
+    /// level -= amount
+    Subtractive,
+    /// level += amount
+    Additive,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Stage {
+    rule: Rule,
+    #[serde(default)]
+    table: Vec<i64>,
+    #[serde(default = "default_divisor")]
+    divisor: i64,
+    /// Values that finish the stage straight away
+    #[serde(default)]
+    instant: Vec<u8>,
+    /// Values that never finish the stage
+    #[serde(default)]
+    endless: Vec<u8>,
+}
+
+fn default_divisor() -> i64 {
+    256
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TableModel {
+    id: String,
+    console: String,
+    name: Option<String>,
+    format_name: String,
+    #[serde(flatten)]
+    simulator: Simulator,
+    attack: Stage,
+    decay: Stage,
+    release: Option<Stage>,
+    #[serde(default)]
+    sustain: Vec<i64>,
+}
+
+impl Stage {
+    /// The level after one tick, None if it overflows
+    fn tick(&self, value: u8, level: i64) -> Option<i64> {
+        let amount = self
+            .table
+            .get(value as usize)
+            .copied()
+            .unwrap_or(value as i64);
+        match self.rule {
+            Rule::Multiplicative => level.checked_mul(amount).map(|l| l / self.divisor),
+            Rule::Subtractive => level.checked_sub(amount),
+            Rule::Additive => level.checked_add(amount),
+        }
+    }
+
+    /// Seconds for the special-cased values, None for the rest
+    fn special(&self, value: u8) -> Option<f64> {
+        if self.instant.contains(&value) {
+            Some(0.0)
+        } else if self.endless.contains(&value) {
+            Some(f64::INFINITY)
+        } else {
+            None
+        }
+    }
+
+    fn check(&self, stage: &str, max_value: u8) -> Result<(), String> {
+        if !self.table.is_empty() && self.table.len() != max_value as usize + 1 {
+            return Err(format!(
+                "{} table has {} entries, expected {}",
+                stage,
+                self.table.len(),
+                max_value as usize + 1
+            ));
+        }
+        if self.rule == Rule::Multiplicative && self.divisor == 0 {
+            return Err(format!("{} divisor can't be 0", stage));
+        }
+        if self.divisor.abs() > MAX_LEVEL || self.table.iter().any(|a| a.abs() > MAX_LEVEL) {
+            return Err(format!(
+                "{} table and divisor must be within ±{}",
+                stage, MAX_LEVEL
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl TableModel {
+    pub fn load(path: &Path) -> Result<TableModel, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let model: TableModel = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str(&text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(&text).map_err(|e| e.to_string())?
+        };
+        model.check()?;
+        Ok(model)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        self.simulator.check()?;
+        let max_value = self.simulator.max_value;
+        if self.sustain.iter().any(|level| level.abs() > MAX_LEVEL) {
+            return Err(format!("sustain table must be within ±{}", MAX_LEVEL));
+        }
+        if !self.sustain.is_empty() && self.sustain.len() != max_value as usize + 1 {
+            return Err(format!(
+                "sustain table has {} entries, expected {}",
+                self.sustain.len(),
+                max_value as usize + 1
+            ));
+        }
+        self.attack.check("attack", max_value)?;
+        self.decay.check("decay", max_value)?;
+        if let Some(release) = &self.release {
+            release.check("release", max_value)?;
+        }
+        Ok(())
+    }
+
+    fn release_stage(&self) -> &Stage {
+        self.release.as_ref().unwrap_or(&self.decay)
+    }
+
+    fn attack_seconds(&self, value: u8) -> f64 {
+        let value = value.min(self.simulator.max_value);
+        if let Some(seconds) = self.attack.special(value) {
+            return seconds;
+        }
+        self.simulator
+            .attack_time(|level| self.attack.tick(value, level))
+    }
+
+    fn fall_seconds(&self, stage: &Stage, value: u8) -> f64 {
+        let value = value.min(self.simulator.max_value);
+        if let Some(seconds) = stage.special(value) {
+            return seconds;
+        }
+        self.simulator.fall_time(|level| stage.tick(value, level))
+    }
+
+    fn sustain_decibels(&self, value: u8) -> f64 {
+        let level = match self.sustain.get(value as usize) {
+            Some(&level) => level,
+            None => self.simulator.linear_level(value),
+        };
+        self.simulator.decibels(level)
+    }
+}
+
+impl EnvelopeModel for TableModel {
+    fn id(&self) -> &str {
+        &self.id
+    }
+
+    fn console(&self) -> &str {
+        &self.console
+    }
+
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.console)
+    }
+
+    fn format_name(&self) -> &str {
+        &self.format_name
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        self.simulator.ticks_per_second
+    }
+
+    fn max_value(&self) -> u8 {
+        self.simulator.max_value
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        self.attack_seconds(values.attack)
+    }
+
+    fn decay_time(&self, values: &Values) -> f64 {
+        self.fall_seconds(&self.decay, values.decay)
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        self.sustain_decibels(values.sustain)
+    }
+
+    fn release_time(&self, values: &Values) -> f64 {
+        self.fall_seconds(self.release_stage(), values.release)
+    }
+
+    fn find_attack(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.simulator
+            .first_past(times.attack, |i| self.attack_seconds(i))
+    }
+
+    fn find_decay(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.simulator
+            .first_past(times.decay, |i| self.fall_seconds(&self.decay, i))
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.simulator
+            .first_past(times.sustain, |i| self.sustain_decibels(i))
+    }
+
+    fn find_release(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        self.simulator.first_past(times.release, |i| {
+            self.fall_seconds(self.release_stage(), i)
+        })
+    }
+}
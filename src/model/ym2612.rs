@@ -0,0 +1,222 @@
+//! YM2612 (Sega Genesis) FM operator envelope generator, following MAME's fm.c
+//!
+//! The four fields are AR, D1R, D1L and RR. D2R and key scale are parameters.
+
+use super::{EnvelopeModel, Parameter, Times, Values};
+
+/// The envelope generator is clocked once every 3 samples of 7670453 / 144 Hz (NTSC)
+pub const TPS_YM2612: f64 = 7670453.0 / 144.0 / 3.0;
+
+/// Attenuation is 10 bits, 0 is full volume and 1023 is silence
+const MAX_ATTENUATION: i32 = 1023;
+const DB_PER_STEP: f64 = 96.0 / 1024.0;
+
+/// Key code of A4 (block 4, F-number 1084), used as the reference note for key scaling
+const KEY_CODE: usize = 18;
+
+const RATE_PATTERNS: [[i32; 8]; 4] = [
+    [0, 1, 0, 1, 0, 1, 0, 1],
+    [0, 1, 0, 1, 1, 1, 0, 1],
+    [0, 1, 1, 1, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 1],
+];
+const HIGH_RATE_PATTERNS: [[i32; 8]; 4] = [
+    [1, 1, 1, 1, 1, 1, 1, 1],
+    [1, 1, 1, 2, 1, 1, 1, 2],
+    [1, 2, 1, 2, 1, 2, 1, 2],
+    [1, 2, 2, 2, 1, 2, 2, 2],
+];
+
+/// Effective 6-bit rate from a 5-bit register rate and the operator's key scale
+fn rate(register: u8, key_scale: u8) -> usize {
+    if register == 0 {
+        0
+    } else {
+        let ks_offset = KEY_CODE >> (3 - key_scale.min(3));
+        (2 * register as usize + ks_offset).min(63)
+    }
+}
+
+/// How much the attenuation moves on a given EG clock, or None if the counter doesn't hit
+fn increment(rate: usize, counter: u32) -> Option<i32> {
+    let shift = if rate < 48 { 11 - rate / 4 } else { 0 };
+    if counter & ((1 << shift) - 1) != 0 {
+        return None;
+    }
+    let cycle = ((counter >> shift) & 7) as usize;
+    Some(match rate {
+        0 | 1 => 0,
+        2..=47 => RATE_PATTERNS[rate % 4][cycle],
+        48..=59 => HIGH_RATE_PATTERNS[rate % 4][cycle] << ((rate - 48) / 4),
+        _ => 8,
+    })
+}
+
+/// Runs the envelope generator to the end of the stage, returning the elapsed time in seconds
+fn simulate(rate: usize, mut attenuation: i32, attack: bool) -> f64 {
+    if rate < 2 {
+        return f64::INFINITY;
+    }
+    let mut counter = 0_u32;
+    loop {
+        if (attack && attenuation <= 0) || (!attack && attenuation >= MAX_ATTENUATION) {
+            return counter as f64 / TPS_YM2612;
+        }
+        counter += 1;
+        if let Some(inc) = increment(rate, counter) {
+            if attack {
+                attenuation += (!attenuation * inc) >> 4;
+            } else {
+                attenuation += inc;
+            }
+        }
+    }
+}
+
+/// AR 0-31
+fn attack_time(ar: u8, key_scale: u8) -> f64 {
+    let rate = rate(ar.min(31), key_scale);
+    if rate >= 62 {
+        0.0 // Attack is skipped entirely
+    } else {
+        simulate(rate, MAX_ATTENUATION, true)
+    }
+}
+
+/// D1R, D2R 0-31; the time it would take to fall from full volume to silence
+fn decay_time(dr: u8, key_scale: u8) -> f64 {
+    simulate(rate(dr.min(31), key_scale), 0, false)
+}
+
+/// D1L 0-15 as decibels of attenuation, 15 jumps to the bottom of the range
+fn sustain_level(d1l: u8) -> f64 {
+    let d1l = d1l.min(15) as i32;
+    let steps = if d1l == 15 { 31 * 32 } else { d1l * 32 };
+    steps as f64 * DB_PER_STEP
+}
+
+/// RR 0-15, which is doubled and given an extra bit to line up with the 5-bit rates
+fn release_time(rr: u8, key_scale: u8) -> f64 {
+    simulate(rate(rr.min(15) * 2 + 1, key_scale), 0, false)
+}
+
+pub struct Ym2612;
+
+const SUSTAIN_RATE: usize = 0;
+const KEY_SCALE: usize = 1;
+
+fn key_scale(parameters: &[u8]) -> u8 {
+    parameters.get(KEY_SCALE).copied().unwrap_or(0)
+}
+
+impl EnvelopeModel for Ym2612 {
+    fn id(&self) -> &str {
+        "ym2612"
+    }
+
+    fn console(&self) -> &str {
+        "Genesis"
+    }
+
+    fn name(&self) -> &str {
+        "YM2612"
+    }
+
+    fn format_name(&self) -> &str {
+        "YM2612"
+    }
+
+    fn ticks_per_second(&self) -> f64 {
+        TPS_YM2612
+    }
+
+    fn max_value(&self) -> u8 {
+        31
+    }
+
+    /// D1L and RR are 4 bits
+    fn max_sustain(&self) -> u8 {
+        15
+    }
+
+    fn max_release(&self) -> u8 {
+        15
+    }
+
+    fn parameters(&self) -> &'static [Parameter] {
+        &[
+            Parameter {
+                name: "D2R",
+                max: 31,
+            },
+            Parameter {
+                name: "Key scale",
+                max: 3,
+            },
+        ]
+    }
+
+    fn attack_time(&self, values: &Values) -> f64 {
+        attack_time(values.attack, key_scale(&values.parameters))
+    }
+
+    fn decay_time(&self, values: &Values) -> f64 {
+        decay_time(values.decay, key_scale(&values.parameters))
+    }
+
+    fn sustain_level(&self, values: &Values) -> f64 {
+        sustain_level(values.sustain)
+    }
+
+    fn release_time(&self, values: &Values) -> f64 {
+        release_time(values.release, key_scale(&values.parameters))
+    }
+
+    fn find_attack(&self, times: &Times, parameters: &[u8]) -> u8 {
+        for i in 1..=31_u8 {
+            if attack_time(i, key_scale(parameters)) < times.attack {
+                return i;
+            }
+        }
+        31
+    }
+
+    fn find_decay(&self, times: &Times, parameters: &[u8]) -> u8 {
+        for i in 1..=31_u8 {
+            if decay_time(i, key_scale(parameters)) < times.decay {
+                return i;
+            }
+        }
+        31
+    }
+
+    fn find_sustain(&self, times: &Times, _parameters: &[u8]) -> u8 {
+        for i in 0..15_u8 {
+            // D1L moves in 3dB steps, so round to the nearest one
+            if sustain_level(i) + 1.5 >= times.sustain {
+                return i;
+            }
+        }
+        15
+    }
+
+    fn find_release(&self, times: &Times, parameters: &[u8]) -> u8 {
+        for i in 1..=15_u8 {
+            if release_time(i, key_scale(parameters)) < times.release {
+                return i;
+            }
+        }
+        15
+    }
+
+    /// D2R keeps fading after the sustain level is reached, which SF2 can't express
+    fn extra_results(&self, values: &Values) -> Vec<(&'static str, f64)> {
+        match values.parameters.get(SUSTAIN_RATE) {
+            Some(&d2r) if d2r != 0 => vec![(
+                "Sustain decay",
+                decay_time(d2r, key_scale(&values.parameters)),
+            )],
+            _ => Vec::new(),
+        }
+    }
+}
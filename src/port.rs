@@ -0,0 +1,161 @@
+//! Porting an envelope from one console to another through SF2 times
+
+use crate::model::{EnvelopeModel, Times, Values};
+
+pub struct Port {
+    /// The original envelope in SF2 units
+    pub times: Times,
+    /// Closest values on the other console
+    pub values: Values,
+    /// What those values come out to, to compare against `times`
+    pub ported_times: Times,
+}
+
+pub fn port(from: &dyn EnvelopeModel, to: &dyn EnvelopeModel, values: &Values) -> Port {
+    let times = from.to_times(values);
+    let parameters = vec![0; to.parameters().len()];
+    let ported = to.to_values(&times, &parameters);
+    let ported_times = to.to_times(&ported);
+    Port {
+        times,
+        values: ported,
+        ported_times,
+    }
+}
+
+/// How far off the ported time is, with matching infinities counting as spot on
+fn error(original: f64, ported: f64) -> f64 {
+    if original == ported {
+        0.0
+    } else {
+        ported - original
+    }
+}
+
+/// One line per stage: the values on both consoles, their times and the difference
+pub fn describe(
+    from: &dyn EnvelopeModel,
+    to: &dyn EnvelopeModel,
+    values: &Values,
+    port: &Port,
+) -> String {
+    let mut rows = vec![(
+        "Attack",
+        values.attack,
+        port.values.attack,
+        port.times.attack,
+        port.ported_times.attack,
+    )];
+    if from.has_hold() || to.has_hold() {
+        rows.push((
+            "Hold",
+            values.hold,
+            port.values.hold,
+            port.times.hold,
+            port.ported_times.hold,
+        ));
+    }
+    rows.extend([
+        (
+            "Decay",
+            values.decay,
+            port.values.decay,
+            port.times.decay,
+            port.ported_times.decay,
+        ),
+        (
+            "Sustain",
+            values.sustain,
+            port.values.sustain,
+            port.times.sustain,
+            port.ported_times.sustain,
+        ),
+        (
+            "Release",
+            values.release,
+            port.values.release,
+            port.times.release,
+            port.ported_times.release,
+        ),
+    ]);
+    let mut result = format!("{} -> {}", from.name(), to.name());
+    for (stage, value, ported_value, time, ported_time) in rows {
+        result += &format!(
+            " \n{}: {} -> {} ({:.3} -> {:.3}, {:+.3})",
+            stage,
+            value,
+            ported_value,
+            time,
+            ported_time,
+            error(time, ported_time)
+        );
+    }
+    result
+}
+
+/// The console and, if it isn't the console's only engine, the engine
+fn label(models: &[Box<dyn EnvelopeModel>], model: &dyn EnvelopeModel) -> String {
+    let engines = models
+        .iter()
+        .filter(|m| m.console() == model.console())
+        .count();
+    if engines > 1 && model.name() != model.console() {
+        format!("{} {}", model.console(), model.name())
+    } else {
+        model.console().to_string()
+    }
+}
+
+fn stages(has_hold: bool, values: [String; 5]) -> String {
+    let [attack, hold, decay, sustain, release] = values;
+    let hold = if has_hold {
+        format!(" H {}", hold)
+    } else {
+        "".to_string()
+    };
+    format!(
+        "A {}{} D {} S {} R {}",
+        attack, hold, decay, sustain, release
+    )
+}
+
+/// The envelope as SF2 times and ported to every other model, one line each
+pub fn matrix(models: &[Box<dyn EnvelopeModel>], from: usize, values: &Values) -> String {
+    let times = models[from].to_times(values);
+    let mut result = format!(
+        "SF2: {}",
+        stages(
+            models[from].has_hold(),
+            [
+                times.attack,
+                times.hold,
+                times.decay,
+                times.sustain,
+                times.release
+            ]
+            .map(|time| format!("{:.3}", time))
+        )
+    );
+    for (i, model) in models.iter().enumerate() {
+        if i == from {
+            continue;
+        }
+        let ported = model.to_values(&times, &vec![0; model.parameters().len()]);
+        result += &format!(
+            " \n{}: {}",
+            label(models, model.as_ref()),
+            stages(
+                model.has_hold(),
+                [
+                    ported.attack,
+                    ported.hold,
+                    ported.decay,
+                    ported.sustain,
+                    ported.release
+                ]
+                .map(|value| value.to_string())
+            )
+        );
+    }
+    result
+}
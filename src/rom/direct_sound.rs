@@ -0,0 +1,112 @@
+//! MP2K DirectSound samples, from a ROM or a decomp checkout
+//!
+//! Samples start with a 16-byte header: the type, a status with the loop flag, the pitch (the
+//! sample rate at middle C times 1024), the loop start and the length, followed by signed 8-bit
+//! PCM. Decomps keep them in `sound/direct_sound_samples` as `.bin` files with the same header, or
+//! as `.wav` files converted at build time, listed in `sound/direct_sound_data.inc`.
+
+use std::{collections::HashMap, fs, path::Path};
+
+use super::{Read, Wave};
+
+/// Set in the status when the sample loops
+const LOOP: u16 = 0xC000;
+
+const DATA_FILE: &str = "sound/direct_sound_data.inc";
+
+/// The sample at the start of `data`, None for Pokémon's compressed samples
+pub fn read(data: &[u8]) -> Option<Wave> {
+    if data.u16_at(0)? != 0 {
+        return None;
+    }
+    let pitch = data.u32_at(4)?;
+    let loop_start = data.u32_at(8)?;
+    let size = data.u32_at(12)? as usize;
+    let pcm = data.get(16..16 + size)?;
+    Some(Wave {
+        rate: (pitch as f64 / 1024.0).round() as u32,
+        data: pcm.iter().map(|&b| (b as i8 as i16) << 8).collect(),
+        loop_start: (data.u16_at(2)? & LOOP != 0).then_some(loop_start),
+    })
+}
+
+/// A `.wav` as wav2agb reads it: the first channel, the loop from the `smpl` chunk and the pitch
+/// from an `agbp` chunk or the `smpl` chunk's unity note
+pub fn read_wav(wav: &[u8]) -> Option<Wave> {
+    if wav.get(..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let (mut format, mut pcm, mut sampler, mut pitch) = (None, None, None, None);
+    let mut at = 12;
+    while let Some(size) = wav.u32_at(at + 4) {
+        let data = wav.get(at + 8..(at + 8).saturating_add(size as usize))?;
+        match wav.get(at..at + 4)? {
+            b"fmt " => format = Some(data),
+            b"data" => pcm = Some(data),
+            b"smpl" => sampler = Some(data),
+            b"agbp" => pitch = data.u32_at(0),
+            _ => {}
+        }
+        at += 8 + size as usize + size as usize % 2;
+    }
+    let format = format?;
+    let channels = format.u16_at(2)?.max(1) as usize;
+    let rate = format.u32_at(4)?;
+    let bits = format.u16_at(14)?;
+    let data: Vec<i16> = match bits {
+        8 => pcm?
+            .iter()
+            .step_by(channels)
+            .map(|&b| (b as i16 - 128) << 8)
+            .collect(),
+        16 => pcm?
+            .chunks_exact(2)
+            .step_by(channels)
+            .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+            .collect(),
+        _ => return None,
+    };
+    let unity = sampler.and_then(|s| s.u32_at(12)).unwrap_or(60);
+    let loop_start = sampler
+        .filter(|s| s.u32_at(28).is_some_and(|loops| loops > 0))
+        .and_then(|s| s.u32_at(36 + 8));
+    let rate = match pitch {
+        Some(pitch) => pitch as f64 / 1024.0,
+        None => rate as f64 * 2_f64.powf((60.0 - unity as f64) / 12.0),
+    };
+    Some(Wave {
+        rate: rate.round() as u32,
+        data,
+        loop_start,
+    })
+}
+
+/// Every sample in a decomp checkout, by the label voicegroups use. `.bin` files that haven't
+/// been built yet are read from the `.wav` they come from.
+pub fn decomp_samples(root: &Path) -> Result<HashMap<String, Wave>, String> {
+    let mut samples = HashMap::new();
+    let text = fs::read_to_string(root.join(DATA_FILE))
+        .map_err(|e| format!("Couldn't read {}: {}", root.join(DATA_FILE).display(), e))?;
+    let mut label = None;
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_suffix(':') {
+            label = Some(name.trim_end_matches(':'));
+        } else if let Some(path) = line
+            .strip_prefix(".incbin")
+            .map(|path| path.trim().trim_matches('"'))
+        {
+            let path = root.join(path);
+            let wave = match fs::read(&path) {
+                Ok(bin) => read(&bin),
+                Err(_) => fs::read(path.with_extension("wav"))
+                    .ok()
+                    .and_then(|wav| read_wav(&wav)),
+            };
+            if let (Some(label), Some(wave)) = (label.take(), wave) {
+                samples.insert(label.to_string(), wave);
+            }
+        }
+    }
+    Ok(samples)
+}
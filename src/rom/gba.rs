@@ -0,0 +1,282 @@
+//! MP2K voicegroups in GBA ROMs
+//!
+//! The song table is found the way Sappy and saptapper find it, through the pointer after the
+//! engine's `SelectSong` code, falling back to looking for a run of valid song headers. Every song
+//! header names its voicegroup, and voicegroups can point to keysplit and drum voicegroups of
+//! their own.
+
+use std::collections::BTreeMap;
+
+use crate::model::Values;
+
+use super::Read;
+
+/// Where the cartridge is mapped in memory
+const ROM_START: u32 = 0x0800_0000;
+
+/// The start of `SelectSong`, followed 40 bytes later by the song table pointer
+const SELECT_SONG: [u8; 30] = [
+    0x00, 0xB5, 0x00, 0x04, 0x07, 0x4A, 0x08, 0x49, 0x40, 0x0B, 0x40, 0x18, 0x83, 0x88, 0x59, 0x00,
+    0xC9, 0x18, 0x89, 0x00, 0x89, 0x18, 0x0A, 0x68, 0x01, 0x68, 0x10, 0x1C, 0x00, 0xF0,
+];
+const SONG_TABLE_POINTER: usize = 40;
+
+/// Valid song headers in a row before a guess is taken as the song table
+const MIN_SONGS: usize = 8;
+
+const VOICE_SIZE: usize = 12;
+const VOICES: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneKind {
+    DirectSound,
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl ToneKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneKind::DirectSound => "DirectSound",
+            ToneKind::Square1 => "Square 1",
+            ToneKind::Square2 => "Square 2",
+            ToneKind::Wave => "Wave",
+            ToneKind::Noise => "Noise",
+        }
+    }
+
+    /// The model that reads this kind's envelope
+    pub fn model_id(&self) -> &'static str {
+        match self {
+            ToneKind::DirectSound => "gba",
+            _ => "cgb",
+        }
+    }
+}
+
+/// A voice that plays a sound itself
+#[derive(Clone, Debug)]
+pub struct Tone {
+    pub kind: ToneKind,
+    /// Fixed pitch, or "no resample" for DirectSound
+    pub fixed: bool,
+    /// Note the voice plays at when it's in a drum voicegroup
+    pub key: u8,
+    /// 0 for centred, and always for Square 1, whose voices have a sweep in its place
+    pub pan: u8,
+    /// Sample for DirectSound and Wave, duty cycle or noise period otherwise
+    pub data: u32,
+    pub values: Values,
+}
+
+#[derive(Clone, Debug)]
+pub enum Voice {
+    Tone(Tone),
+    /// Picks a voice from another voicegroup by key through a 128-byte table
+    Keysplit {
+        voicegroup: u32,
+        table: u32,
+    },
+    /// Every key plays that key's voice from another voicegroup
+    Drums {
+        voicegroup: u32,
+    },
+}
+
+pub struct Voicegroup {
+    pub address: u32,
+    /// Voices by program, or by key in drum voicegroups
+    pub voices: Vec<(u8, Voice)>,
+}
+
+pub struct Scan {
+    pub song_table: u32,
+    /// Song header addresses, in song table order
+    pub songs: Vec<u32>,
+    /// Every voicegroup a song or voice uses, by address
+    pub voicegroups: BTreeMap<u32, Voicegroup>,
+}
+
+impl Scan {
+    pub fn voice(&self, voicegroup: u32, program: u8) -> Option<&Voice> {
+        self.voicegroups
+            .get(&voicegroup)?
+            .voices
+            .iter()
+            .find(|(p, _)| *p == program)
+            .map(|(_, voice)| voice)
+    }
+
+    /// The tone a program plays a key with, going through keysplits and drums
+    pub fn tone(&self, rom: &[u8], voicegroup: u32, program: u8, key: u8) -> Option<&Tone> {
+        let voice = match self.voice(voicegroup, program)? {
+            Voice::Tone(tone) => return Some(tone),
+            Voice::Keysplit { voicegroup, table } => {
+                let index = rom.u8_at(offset(rom, *table)? + key as usize)?;
+                self.voice(*voicegroup, index)?
+            }
+            Voice::Drums { voicegroup } => self.voice(*voicegroup, key)?,
+        };
+        match voice {
+            Voice::Tone(tone) => Some(tone),
+            _ => None,
+        }
+    }
+}
+
+/// The ROM offset for a pointer into the cartridge
+pub fn offset(rom: &[u8], pointer: u32) -> Option<usize> {
+    let offset = pointer.checked_sub(ROM_START)? as usize;
+    (offset < rom.len()).then_some(offset)
+}
+
+fn read_voice(rom: &[u8], address: u32) -> Option<Voice> {
+    let at = offset(rom, address)?;
+    let bytes = rom.get(at..at + VOICE_SIZE)?;
+    let pointer = rom.u32_at(at + 4)?;
+    let values = Values {
+        attack: bytes[8],
+        decay: bytes[9],
+        sustain: bytes[10],
+        release: bytes[11],
+        ..Values::default()
+    };
+    let kind = match bytes[0] & !0x08 {
+        0x00 | 0x10 => ToneKind::DirectSound,
+        0x01 => ToneKind::Square1,
+        0x02 => ToneKind::Square2,
+        0x03 => ToneKind::Wave,
+        0x04 => ToneKind::Noise,
+        0x40 => {
+            let table = rom.u32_at(at + 8)?;
+            offset(rom, pointer)?;
+            offset(rom, table)?;
+            return Some(Voice::Keysplit {
+                voicegroup: pointer,
+                table,
+            });
+        }
+        0x80 => {
+            offset(rom, pointer)?;
+            return Some(Voice::Drums {
+                voicegroup: pointer,
+            });
+        }
+        _ => return None,
+    };
+    match kind {
+        ToneKind::DirectSound | ToneKind::Wave => {
+            offset(rom, pointer)?;
+        }
+        // The Game Boy channels only have 3-bit rates and a 4-bit sustain
+        _ if values.attack > 7 || values.decay > 7 || values.sustain > 15 || values.release > 7 => {
+            return None
+        }
+        _ => {}
+    }
+    Some(Voice::Tone(Tone {
+        kind,
+        fixed: bytes[0] & 0x08 != 0,
+        key: bytes[1],
+        pan: if kind == ToneKind::Square1 {
+            0
+        } else {
+            bytes[3]
+        },
+        data: pointer,
+        values,
+    }))
+}
+
+/// The first `count` voices of a voicegroup, leaving out ones that aren't valid
+fn read_voicegroup(rom: &[u8], address: u32, count: usize) -> Voicegroup {
+    let voices = (0..count.min(VOICES))
+        .filter_map(|program| {
+            let voice = read_voice(rom, address + (program * VOICE_SIZE) as u32)?;
+            Some((program as u8, voice))
+        })
+        .collect();
+    Voicegroup { address, voices }
+}
+
+/// The song header's voicegroup, if it looks like a song header
+pub fn song_voicegroup(rom: &[u8], header: u32) -> Option<u32> {
+    let at = offset(rom, header)?;
+    let tracks = rom.u8_at(at)? as usize;
+    if tracks > 16 {
+        return None;
+    }
+    let voicegroup = rom.u32_at(at + 4)?;
+    offset(rom, voicegroup)?;
+    for track in 0..tracks {
+        offset(rom, rom.u32_at(at + 8 + track * 4)?)?;
+    }
+    Some(voicegroup)
+}
+
+fn find_song_table(rom: &[u8]) -> Option<usize> {
+    if let Some(select_song) = rom
+        .windows(SELECT_SONG.len())
+        .position(|window| window == SELECT_SONG)
+    {
+        if let Some(table) = rom
+            .u32_at(select_song + SONG_TABLE_POINTER)
+            .and_then(|pointer| offset(rom, pointer))
+        {
+            return Some(table);
+        }
+    }
+    (0..rom.len()).step_by(4).find(|&at| {
+        (0..MIN_SONGS).all(|song| {
+            rom.u32_at(at + song * 8)
+                .and_then(|header| song_voicegroup(rom, header))
+                .is_some()
+        })
+    })
+}
+
+pub fn scan(rom: &[u8]) -> Result<Scan, String> {
+    let table = find_song_table(rom).ok_or("Couldn't find an MP2K song table")?;
+    let mut songs = Vec::new();
+    let mut pending = Vec::new();
+    while let Some(header) = rom.u32_at(table + songs.len() * 8) {
+        let Some(voicegroup) = song_voicegroup(rom, header) else {
+            break;
+        };
+        pending.push((voicegroup, VOICES));
+        songs.push(header);
+    }
+
+    let mut voicegroups = BTreeMap::new();
+    // How many voices each voicegroup was read with, as keysplits only need the keys they use
+    let mut counts: BTreeMap<u32, usize> = BTreeMap::new();
+    while let Some((address, count)) = pending.pop() {
+        if counts.get(&address).is_some_and(|&read| read >= count) {
+            continue;
+        }
+        counts.insert(address, count);
+        let voicegroup = read_voicegroup(rom, address, count);
+        for (_, voice) in &voicegroup.voices {
+            match voice {
+                Voice::Keysplit { voicegroup, table } => {
+                    let at = offset(rom, *table).unwrap_or(0);
+                    let count = rom
+                        .get(at..at + VOICES)
+                        .and_then(|keys| keys.iter().max())
+                        .map_or(0, |&max| max as usize + 1);
+                    pending.push((*voicegroup, count));
+                }
+                Voice::Drums { voicegroup } => pending.push((*voicegroup, VOICES)),
+                Voice::Tone(_) => {}
+            }
+        }
+        voicegroups.insert(address, voicegroup);
+    }
+    Ok(Scan {
+        song_table: table as u32 + ROM_START,
+        songs,
+        voicegroups,
+    })
+}
@@ -0,0 +1,89 @@
+//! Reading envelopes straight out of game ROMs
+
+pub mod direct_sound;
+pub mod gba;
+pub mod nds;
+pub mod sdat;
+pub mod song;
+pub mod sseq;
+pub mod swar;
+
+use crate::model::{Times, Values};
+
+/// A sample decoded to 16-bit PCM
+pub struct Wave {
+    pub rate: u32,
+    pub data: Vec<i16>,
+    /// The sample the loop goes back to, which plays until the end
+    pub loop_start: Option<u32>,
+}
+
+/// Little-endian reads that are None past the end of the data
+pub trait Read {
+    fn u8_at(&self, offset: usize) -> Option<u8>;
+    fn u16_at(&self, offset: usize) -> Option<u16>;
+    fn u32_at(&self, offset: usize) -> Option<u32>;
+}
+
+impl Read for [u8] {
+    fn u8_at(&self, offset: usize) -> Option<u8> {
+        self.get(offset).copied()
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes = self.get(offset..offset.checked_add(2)?)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+/// An envelope stage that a song or sequence can change while it plays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+impl Stage {
+    pub fn value(&self, values: &Values) -> u8 {
+        match self {
+            Stage::Attack => values.attack,
+            Stage::Decay => values.decay,
+            Stage::Sustain => values.sustain,
+            Stage::Release => values.release,
+        }
+    }
+
+    pub fn set(&self, values: &mut Values, value: u8) {
+        match self {
+            Stage::Attack => values.attack = value,
+            Stage::Decay => values.decay = value,
+            Stage::Sustain => values.sustain = value,
+            Stage::Release => values.release = value,
+        }
+    }
+
+    pub fn time(&self, times: &Times) -> f64 {
+        match self {
+            Stage::Attack => times.attack,
+            Stage::Decay => times.decay,
+            Stage::Sustain => times.sustain,
+            Stage::Release => times.release,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Attack => "Attack",
+            Stage::Decay => "Decay",
+            Stage::Sustain => "Sustain",
+            Stage::Release => "Release",
+        }
+    }
+}
@@ -0,0 +1,78 @@
+//! NitroFS, the file system inside NDS ROMs
+
+use super::Read;
+
+const FNT_OFFSET: usize = 0x40;
+const FAT_OFFSET: usize = 0x48;
+
+/// Subdirectory ids start here
+const FIRST_DIRECTORY: u16 = 0xF000;
+
+/// Sanity limit on subdirectory nesting, against loops in broken file name tables
+const MAX_DEPTH: usize = 32;
+
+pub struct File<'a> {
+    pub path: String,
+    pub data: &'a [u8],
+}
+
+fn file(rom: &[u8], fat: usize, id: u16) -> Option<&[u8]> {
+    let entry = fat + id as usize * 8;
+    let start = rom.u32_at(entry)? as usize;
+    let end = rom.u32_at(entry + 4)? as usize;
+    rom.get(start..end)
+}
+
+/// Reads a directory and its subdirectories. A broken subdirectory is skipped, as far as it got.
+fn read_directory<'a>(
+    rom: &'a [u8],
+    fnt: usize,
+    fat: usize,
+    directory: u16,
+    path: &str,
+    depth: usize,
+    files: &mut Vec<File<'a>>,
+) -> Result<(), String> {
+    let cut_off = || "The ROM's file system is cut off".to_string();
+    if depth > MAX_DEPTH {
+        return Err("The ROM's directories are nested too deep".to_string());
+    }
+    let index = directory
+        .checked_sub(FIRST_DIRECTORY)
+        .ok_or_else(|| format!("0x{:04X} isn't a directory id", directory))?;
+    let entry = fnt + index as usize * 8;
+    let header = rom.get(entry..entry + 8).ok_or_else(cut_off)?;
+    let mut at = fnt + header.u32_at(0).ok_or_else(cut_off)? as usize;
+    let mut id = header.u16_at(4).ok_or_else(cut_off)?;
+    loop {
+        let kind = rom.u8_at(at).ok_or_else(cut_off)?;
+        if kind == 0 {
+            return Ok(());
+        }
+        let length = (kind & 0x7F) as usize;
+        let name = String::from_utf8_lossy(rom.get(at + 1..at + 1 + length).ok_or_else(cut_off)?);
+        let child = format!("{}/{}", path, name);
+        at += 1 + length;
+        if kind & 0x80 != 0 {
+            let subdirectory = rom.u16_at(at).ok_or_else(cut_off)?;
+            at += 2;
+            let _ = read_directory(rom, fnt, fat, subdirectory, &child, depth + 1, files);
+        } else {
+            if let Some(data) = file(rom, fat, id) {
+                files.push(File { path: child, data });
+            }
+            id = id
+                .checked_add(1)
+                .ok_or("The ROM's file system has too many files")?;
+        }
+    }
+}
+
+/// Every named file in the ROM
+pub fn files(rom: &[u8]) -> Result<Vec<File<'_>>, String> {
+    let fnt = rom.u32_at(FNT_OFFSET).ok_or("The ROM is too short")? as usize;
+    let fat = rom.u32_at(FAT_OFFSET).ok_or("The ROM is too short")? as usize;
+    let mut files = Vec::new();
+    read_directory(rom, fnt, fat, FIRST_DIRECTORY, "", 0, &mut files)?;
+    Ok(files)
+}
@@ -0,0 +1,300 @@
+//! SDAT sound archives and the SBNK instrument banks in them
+
+use crate::model::Values;
+
+use super::{nds, sseq, swar, Read, Wave};
+
+/// INFO and SYMB records, in the order the archive lists them
+const RECORD_SEQ: usize = 0;
+const RECORD_BANK: usize = 2;
+const RECORD_WAVE_ARCHIVE: usize = 3;
+
+/// A sample or PSG sound with its envelope
+#[derive(Clone, Debug)]
+pub struct Note {
+    /// 1 for a sample, 2 for a PSG square wave and 3 for noise
+    pub kind: u8,
+    /// The sample, or the duty cycle of a square wave
+    pub wave: u16,
+    /// Which of the bank's wave archives the sample is in
+    pub archive: u16,
+    pub root_key: u8,
+    pub values: Values,
+    pub pan: u8,
+}
+
+#[derive(Clone, Debug)]
+pub enum Instrument {
+    Single(Note),
+    /// A note for every key from `low`
+    Drums {
+        low: u8,
+        notes: Vec<Note>,
+    },
+    /// Notes for ranges of keys, each up to and including its key
+    KeySplit(Vec<(u8, Note)>),
+}
+
+impl Instrument {
+    /// The note that plays a key
+    pub fn note(&self, key: u8) -> Option<&Note> {
+        match self {
+            Instrument::Single(note) => Some(note),
+            Instrument::Drums { low, notes } => notes.get(key.checked_sub(*low)? as usize),
+            Instrument::KeySplit(regions) => regions
+                .iter()
+                .find(|(high, _)| key <= *high)
+                .map(|(_, note)| note),
+        }
+    }
+
+    /// The first note, for when there's no key to go by
+    pub fn first_note(&self) -> Option<&Note> {
+        match self {
+            Instrument::Single(note) => Some(note),
+            Instrument::Drums { notes, .. } => notes.first(),
+            Instrument::KeySplit(regions) => regions.first().map(|(_, note)| note),
+        }
+    }
+}
+
+pub struct Bank {
+    pub id: u16,
+    pub name: String,
+    pub wave_archives: [u16; 4],
+    pub instruments: Vec<(u16, Instrument)>,
+}
+
+pub struct Sequence {
+    pub name: String,
+    pub bank: u16,
+    /// Volume the whole sequence plays at, 0 to 127
+    pub volume: u8,
+    pub overrides: Vec<sseq::Override>,
+}
+
+pub struct Sdat<'a> {
+    pub path: String,
+    pub data: &'a [u8],
+    pub banks: Vec<Bank>,
+    pub sequences: Vec<Sequence>,
+}
+
+impl Sdat<'_> {
+    pub fn bank(&self, id: u16) -> Option<&Bank> {
+        self.banks.iter().find(|bank| bank.id == id)
+    }
+
+    /// A wave from one of the archive's SWARs
+    pub fn wave(&self, archive: u16, wave: u16) -> Option<Wave> {
+        let entry = info(self.data, RECORD_WAVE_ARCHIVE)?
+            .get(archive as usize)
+            .copied()??;
+        swar::wave(file(self.data, self.data.u16_at(entry)?)?, wave)
+    }
+}
+
+fn read_note(data: &[u8], at: usize, kind: u8) -> Option<Note> {
+    Some(Note {
+        kind,
+        wave: data.u16_at(at)?,
+        archive: data.u16_at(at + 2)?,
+        root_key: data.u8_at(at + 4)?,
+        values: Values {
+            attack: data.u8_at(at + 5)?,
+            decay: data.u8_at(at + 6)?,
+            sustain: data.u8_at(at + 7)?,
+            release: data.u8_at(at + 8)?,
+            ..Values::default()
+        },
+        pan: data.u8_at(at + 9)?,
+    })
+}
+
+/// The instruments in an SBNK file, by program
+pub fn read_bank(sbnk: &[u8]) -> Option<Vec<(u16, Instrument)>> {
+    if sbnk.get(..4)? != b"SBNK" {
+        return None;
+    }
+    let count = sbnk.u32_at(0x38)? as usize;
+    let mut instruments = Vec::new();
+    for program in 0..count {
+        let record = 0x3C + program * 4;
+        let kind = sbnk.u8_at(record)?;
+        let at = sbnk.u16_at(record + 1)? as usize;
+        let instrument = match kind {
+            1..=5 => Instrument::Single(read_note(sbnk, at, kind)?),
+            16 => {
+                let low = sbnk.u8_at(at)?;
+                let high = sbnk.u8_at(at + 1)?;
+                let notes = (0..=high.saturating_sub(low) as usize)
+                    .map(|key| {
+                        let entry = at + 2 + key * 12;
+                        read_note(sbnk, entry + 2, sbnk.u8_at(entry)?)
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Instrument::Drums { low, notes }
+            }
+            17 => {
+                let highs = sbnk.get(at..at + 8)?;
+                let regions = highs
+                    .iter()
+                    .take_while(|&&high| high != 0)
+                    .enumerate()
+                    .map(|(region, &high)| {
+                        let entry = at + 8 + region * 12;
+                        Some((high, read_note(sbnk, entry + 2, sbnk.u8_at(entry)?)?))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Instrument::KeySplit(regions)
+            }
+            _ => continue,
+        };
+        instruments.push((program as u16, instrument));
+    }
+    Some(instruments)
+}
+
+/// A file in the archive's FAT
+pub fn file(sdat: &[u8], id: u16) -> Option<&[u8]> {
+    let fat = sdat.u32_at(0x20)? as usize;
+    if id as u32 >= sdat.u32_at(fat + 8)? {
+        return None;
+    }
+    let entry = fat + 12 + id as usize * 16;
+    let start = sdat.u32_at(entry)? as usize;
+    let size = sdat.u32_at(entry + 4)? as usize;
+    sdat.get(start..start + size)
+}
+
+/// Offsets of a record's entries, relative to the block, None for unused entries
+fn record(sdat: &[u8], block: usize, index: usize) -> Option<Vec<Option<usize>>> {
+    let at = block + sdat.u32_at(block + 8 + index * 4)? as usize;
+    let count = sdat.u32_at(at)? as usize;
+    (0..count)
+        .map(|i| {
+            let offset = sdat.u32_at(at + 4 + i * 4)? as usize;
+            Some((offset != 0).then_some(block + offset))
+        })
+        .collect()
+}
+
+/// The names of a record's entries, if the archive kept its symbols
+pub fn names(sdat: &[u8], index: usize) -> Vec<Option<String>> {
+    let symbols = sdat.u32_at(0x10).unwrap_or(0) as usize;
+    if symbols == 0 || sdat.get(symbols..symbols + 4) != Some(b"SYMB") {
+        return Vec::new();
+    }
+    record(sdat, symbols, index)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|at| {
+            let name = sdat.get(at?..)?;
+            let end = name.iter().position(|&b| b == 0)?;
+            Some(String::from_utf8_lossy(&name[..end]).to_string())
+        })
+        .collect()
+}
+
+/// Info entries of a record, by id
+pub fn info(sdat: &[u8], index: usize) -> Option<Vec<Option<usize>>> {
+    let info = sdat.u32_at(0x18)? as usize;
+    if sdat.get(info..info + 4)? != b"INFO" {
+        return None;
+    }
+    record(sdat, info, index)
+}
+
+pub fn read(path: String, data: &[u8]) -> Option<Sdat<'_>> {
+    if data.get(..4)? != b"SDAT" {
+        return None;
+    }
+    let names = names(data, RECORD_BANK);
+    let mut banks = Vec::new();
+    for (id, entry) in info(data, RECORD_BANK)?.into_iter().enumerate() {
+        let Some(entry) = entry else {
+            continue;
+        };
+        let Some(instruments) = data
+            .u16_at(entry)
+            .and_then(|file_id| file(data, file_id))
+            .and_then(read_bank)
+        else {
+            continue;
+        };
+        let mut wave_archives = [0; 4];
+        for (i, archive) in wave_archives.iter_mut().enumerate() {
+            *archive = data.u16_at(entry + 4 + i * 2).unwrap_or(0xFFFF);
+        }
+        banks.push(Bank {
+            id: id as u16,
+            name: names
+                .get(id)
+                .cloned()
+                .flatten()
+                .unwrap_or(format!("BANK_{}", id)),
+            wave_archives,
+            instruments,
+        });
+    }
+    let names = self::names(data, RECORD_SEQ);
+    let mut sequences = Vec::new();
+    for (id, entry) in info(data, RECORD_SEQ)
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+    {
+        let Some(entry) = entry else {
+            continue;
+        };
+        let Some(overrides) = data
+            .u16_at(entry)
+            .and_then(|file_id| file(data, file_id))
+            .and_then(sseq::overrides)
+        else {
+            continue;
+        };
+        sequences.push(Sequence {
+            name: names
+                .get(id)
+                .cloned()
+                .flatten()
+                .unwrap_or(format!("SEQ_{}", id)),
+            bank: data.u16_at(entry + 4).unwrap_or(0),
+            volume: data.u8_at(entry + 6).unwrap_or(127),
+            overrides,
+        });
+    }
+    Some(Sdat {
+        path,
+        data,
+        banks,
+        sequences,
+    })
+}
+
+/// Every SDAT in an NDS ROM's file system, or found by its header if there's none
+pub fn find(rom: &[u8]) -> Vec<Sdat<'_>> {
+    let mut archives: Vec<Sdat> = nds::files(rom)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|file| file.path.to_lowercase().ends_with(".sdat"))
+        .filter_map(|file| read(file.path, file.data))
+        .collect();
+    if archives.is_empty() {
+        let mut at = 0;
+        while let Some(found) = rom[at..].windows(4).position(|w| w == b"SDAT") {
+            let start = at + found;
+            let size = rom.u32_at(start + 8).unwrap_or(0) as usize;
+            if let Some(sdat) = rom
+                .get(start..start.saturating_add(size))
+                .filter(|_| rom.u16_at(start + 4) == Some(0xFEFF))
+                .and_then(|data| read(format!("0x{:08X}", start), data))
+            {
+                archives.push(sdat);
+            }
+            at = start + 4;
+        }
+    }
+    archives
+}
@@ -0,0 +1,306 @@
+//! Envelope overrides in MP2K songs
+//!
+//! The extended command `XCMD` followed by `xATTA`, `xDECA`, `xSUST` or `xRELE` changes that
+//! stage of the track's voice until the next `VOICE` command. Songs are read either from a ROM,
+//! through the song header's track pointers, or from a decomp's `.s` song file. Each track is
+//! followed once, without repeating loops, to find where they happen.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{gba, Read, Stage};
+
+/// Keeps a broken song from running forever
+const MAX_COMMANDS: usize = 100_000;
+const MAX_CALL_DEPTH: usize = 3;
+
+/// Ticks waited by `W00` (0x80) to `W96` (0xB0), the note lengths of `N01` to `N96` follow it
+const WAITS: [u32; 49] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 28,
+    30, 32, 36, 40, 42, 44, 48, 52, 54, 56, 60, 64, 66, 68, 72, 76, 78, 80, 84, 88, 90, 92, 96,
+];
+
+const FINE: u8 = 0xB1;
+const GOTO: u8 = 0xB2;
+const PATT: u8 = 0xB3;
+const PEND: u8 = 0xB4;
+const REPT: u8 = 0xB5;
+const MEMACC: u8 = 0xB9;
+const VOICE: u8 = 0xBD;
+const XCMD: u8 = 0xCD;
+const EOT: u8 = 0xCE;
+const TIE: u8 = 0xCF;
+/// `N01`, the first of the notes
+const NOTE: u8 = 0xD0;
+
+/// `XCMD` arguments, as in `MPlayDef.s`
+const X_STAGES: [(u8, &str, Stage); 4] = [
+    (0x04, "xATTA", Stage::Attack),
+    (0x05, "xDECA", Stage::Decay),
+    (0x06, "xSUST", Stage::Sustain),
+    (0x07, "xRELE", Stage::Release),
+];
+
+/// Bytes after each `XCMD` argument, None for the ones the engine ends the track on
+fn x_size(kind: u8) -> Option<usize> {
+    match kind {
+        // xWAVE's pointer and the undocumented 0x0D
+        0x01 | 0x0D => Some(4),
+        0x02 | 0x04..=0x0B => Some(1),
+        0x0C => Some(2),
+        _ => None,
+    }
+}
+
+pub struct Override {
+    /// Counted from 1, as decomp song files do
+    pub track: u8,
+    pub tick: u32,
+    pub stage: Stage,
+    pub value: u8,
+    pub program: u8,
+    /// The next note the track plays, which picks the voice of drums and keysplits
+    pub key: Option<u8>,
+}
+
+struct Track<'a> {
+    rom: &'a [u8],
+    number: u8,
+    position: usize,
+    tick: u32,
+    program: u8,
+    key: u8,
+    calls: Vec<usize>,
+    jumps: HashSet<usize>,
+}
+
+impl Track<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let value = self.rom.u8_at(self.position)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    /// An optional argument, which is any byte below 0x80
+    fn argument(&mut self) -> Option<u8> {
+        let value = self.rom.u8_at(self.position).filter(|&byte| byte < 0x80)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    fn pointer(&mut self) -> Option<usize> {
+        let pointer = self.rom.u32_at(self.position)?;
+        self.position += 4;
+        gba::offset(self.rom, pointer)
+    }
+
+    /// A note's key, velocity and, for `N01` to `N96`, extra length, each left out to repeat the
+    /// last
+    fn note(&mut self, extra_length: bool) {
+        if let Some(key) = self.argument() {
+            self.key = key;
+            if self.argument().is_some() && extra_length {
+                self.argument();
+            }
+        }
+    }
+
+    fn run(&mut self, overrides: &mut Vec<Override>) -> Option<()> {
+        let mut waiting: Vec<usize> = Vec::new();
+        let mut running = 0;
+        for _ in 0..MAX_COMMANDS {
+            // Arguments without a command repeat the last one that took arguments
+            let command = match self.rom.u8_at(self.position)? {
+                byte if byte < 0x80 => running,
+                byte => {
+                    self.position += 1;
+                    byte
+                }
+            };
+            match command {
+                0x80..=0xB0 => self.tick += WAITS[(command - 0x80) as usize],
+                FINE => return Some(()),
+                GOTO => {
+                    let position = self.pointer()?;
+                    // Jumping back or somewhere twice is a loop
+                    if position < self.position || !self.jumps.insert(position) {
+                        return Some(());
+                    }
+                    self.position = position;
+                }
+                PATT => {
+                    let position = self.pointer()?;
+                    if self.calls.len() == MAX_CALL_DEPTH {
+                        return Some(());
+                    }
+                    self.calls.push(self.position);
+                    self.position = position;
+                }
+                PEND => {
+                    if let Some(position) = self.calls.pop() {
+                        self.position = position;
+                    }
+                }
+                REPT => self.position += 5,
+                MEMACC => self.position += 3,
+                // `PRIO`, `TEMPO` and `KEYSH` don't keep running status
+                0xBA..=0xBC => self.position += 1,
+                0xBD..=0xC5 | 0xC8 => {
+                    let value = self.u8()?;
+                    if command == VOICE {
+                        self.program = value;
+                    }
+                    running = command;
+                }
+                XCMD => {
+                    let kind = self.u8()?;
+                    let size = x_size(kind)?;
+                    if let Some((_, _, stage)) = X_STAGES.iter().find(|x| x.0 == kind) {
+                        let value = self.u8()?;
+                        waiting.push(overrides.len());
+                        overrides.push(Override {
+                            track: self.number,
+                            tick: self.tick,
+                            stage: *stage,
+                            value,
+                            program: self.program,
+                            key: None,
+                        });
+                    } else {
+                        self.position += size;
+                    }
+                    running = command;
+                }
+                EOT => {
+                    self.argument();
+                    running = command;
+                }
+                TIE => {
+                    self.note(false);
+                    for i in waiting.drain(..) {
+                        overrides[i].key = Some(self.key);
+                    }
+                    running = command;
+                }
+                NOTE.. => {
+                    self.note(true);
+                    for i in waiting.drain(..) {
+                        overrides[i].key = Some(self.key);
+                    }
+                    running = command;
+                }
+                _ => return Some(()),
+            }
+        }
+        Some(())
+    }
+}
+
+/// Every envelope override in the song with this header, by track and then by tick
+pub fn overrides(rom: &[u8], header: u32) -> Option<Vec<Override>> {
+    let at = gba::offset(rom, header)?;
+    let tracks = rom.u8_at(at)? as usize;
+    let mut overrides = Vec::new();
+    for number in 0..tracks {
+        let Some(position) = rom
+            .u32_at(at + 8 + number * 4)
+            .and_then(|pointer| gba::offset(rom, pointer))
+        else {
+            continue;
+        };
+        let mut track = Track {
+            rom,
+            number: number as u8 + 1,
+            position,
+            tick: 0,
+            program: 0,
+            key: 60,
+            calls: Vec::new(),
+            jumps: HashSet::new(),
+        };
+        // A cut off track still has whatever was found before the end
+        track.run(&mut overrides);
+    }
+    Some(overrides)
+}
+
+/// A number as mid2agb writes it, None for expressions like `c_v+10`
+fn number(token: &str) -> Option<u8> {
+    match token.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16).ok(),
+        None => token.parse().ok(),
+    }
+}
+
+/// Every envelope override in a decomp `.s` song file. Tracks start at the
+/// `@*** Track 1 (Midi-Chn.1) ***@` comments mid2agb writes, or the whole file is one track.
+pub fn assembly_overrides(text: &str) -> Vec<Override> {
+    let mut overrides = Vec::new();
+    let mut track = 1;
+    let mut tick = 0;
+    let mut program = 0;
+    // Where each label is, and where the pattern starting at it ends
+    let mut labels: HashMap<&str, u32> = HashMap::new();
+    let mut ends: HashMap<&str, u32> = HashMap::new();
+    let mut last_label = None;
+    let mut previous = "";
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix('@') {
+            if let Some(number) = comment
+                .trim_matches(|c| c == '*' || c == '@' || c == ' ')
+                .strip_prefix("Track ")
+                .and_then(|rest| rest.split_whitespace().next())
+                .and_then(number)
+            {
+                track = number;
+                tick = 0;
+                program = 0;
+            }
+            continue;
+        }
+        let line = line.split('@').next().unwrap_or("").trim();
+        if let Some(label) = line.strip_suffix(':') {
+            labels.insert(label, tick);
+            last_label = Some(label);
+            continue;
+        }
+        let tokens = if let Some(bytes) = line.strip_prefix(".byte") {
+            bytes.split(',').map(str::trim)
+        } else if let Some(word) = line.strip_prefix(".word") {
+            if previous == "PATT" {
+                let label = word.trim();
+                if let (Some(start), Some(end)) = (labels.get(label), ends.get(label)) {
+                    tick += end - start;
+                }
+            }
+            previous = "";
+            continue;
+        } else {
+            continue;
+        };
+        for token in tokens {
+            if let Some(stage) = X_STAGES.iter().find(|x| x.1 == previous).map(|x| x.2) {
+                if let Some(value) = number(token) {
+                    overrides.push(Override {
+                        track,
+                        tick,
+                        stage,
+                        value,
+                        program,
+                        key: None,
+                    });
+                }
+            } else if previous == "VOICE" {
+                program = number(token).unwrap_or(program);
+            } else if token == "PEND" {
+                if let Some(label) = last_label.take() {
+                    ends.insert(label, tick);
+                }
+            } else if let Some(wait) = token.strip_prefix('W').and_then(|w| w.parse::<u32>().ok()) {
+                tick += wait;
+            }
+            previous = token;
+        }
+    }
+    overrides
+}
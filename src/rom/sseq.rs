@@ -0,0 +1,230 @@
+//! Envelope overrides in SSEQ sequences
+//!
+//! Commands 0xD0 to 0xD3 set the attack, decay, sustain and release of every note a track plays
+//! after them, with 255 going back to the instrument's own value. Each track is followed once,
+//! without repeating loops, to find where they happen.
+
+use std::collections::HashSet;
+
+use super::{Read, Stage};
+
+/// Where the commands start, relative to the SSEQ
+const DATA_OFFSET: usize = 0x18;
+
+/// Keeps a broken sequence from running forever
+const MAX_COMMANDS: usize = 100_000;
+const MAX_CALL_DEPTH: usize = 8;
+
+pub struct Override {
+    pub track: u8,
+    pub tick: u32,
+    pub stage: Stage,
+    /// None when the value is random or comes from a variable, 255 for the instrument's own
+    pub value: Option<u8>,
+    pub program: u16,
+    /// The next note the track plays, which picks the region of drum sets and key splits
+    pub key: Option<u8>,
+}
+
+/// How a command's last argument is changed by a prefix
+#[derive(Clone, Copy, PartialEq)]
+enum Prefix {
+    None,
+    /// Two signed 16-bit bounds
+    Random,
+    /// A variable number
+    Variable,
+}
+
+struct Track<'a> {
+    data: &'a [u8],
+    number: u8,
+    position: usize,
+    tick: u32,
+    program: u16,
+    note_wait: bool,
+    calls: Vec<usize>,
+    jumps: HashSet<usize>,
+}
+
+impl Track<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let value = self.data.u8_at(self.position)?;
+        self.position += 1;
+        Some(value)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let value = self.data.u16_at(self.position)?;
+        self.position += 2;
+        Some(value)
+    }
+
+    fn u24(&mut self) -> Option<usize> {
+        let low = self.u16()? as usize;
+        Some(low | (self.u8()? as usize) << 16)
+    }
+
+    fn variable_length(&mut self) -> Option<u32> {
+        let mut value = 0_u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = value << 7 | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        Some(value)
+    }
+
+    /// The last argument of a command, None if a prefix makes it unknowable
+    fn last_argument(
+        &mut self,
+        prefix: Prefix,
+        read: fn(&mut Self) -> Option<u32>,
+    ) -> Option<Option<u32>> {
+        match prefix {
+            Prefix::None => read(self).map(Some),
+            Prefix::Random => {
+                self.position += 4;
+                Some(None)
+            }
+            Prefix::Variable => {
+                self.position += 1;
+                Some(None)
+            }
+        }
+    }
+
+    /// Runs the track, adding the tracks it opens to `open`
+    fn run(
+        &mut self,
+        overrides: &mut Vec<Override>,
+        open: &mut Vec<(u8, usize, u32)>,
+    ) -> Option<()> {
+        let mut waiting: Vec<usize> = Vec::new();
+        let mut prefix = Prefix::None;
+        for _ in 0..MAX_COMMANDS {
+            let command = self.u8()?;
+            let this_prefix = std::mem::replace(&mut prefix, Prefix::None);
+            match command {
+                0x00..=0x7F => {
+                    self.u8()?;
+                    let duration = self.last_argument(this_prefix, |t| t.variable_length())?;
+                    for i in waiting.drain(..) {
+                        overrides[i].key = Some(command);
+                    }
+                    if self.note_wait {
+                        self.tick += duration.unwrap_or(0);
+                    }
+                }
+                0x80 => {
+                    let rest = self.last_argument(this_prefix, |t| t.variable_length())?;
+                    self.tick += rest.unwrap_or(0);
+                }
+                0x81 => {
+                    if let Some(program) =
+                        self.last_argument(this_prefix, |t| t.variable_length())?
+                    {
+                        self.program = program as u16;
+                    }
+                }
+                0x93 => {
+                    let number = self.u8()?;
+                    let offset = self.u24()?;
+                    open.push((number, offset, self.tick));
+                }
+                0x94 => {
+                    let offset = self.u24()?;
+                    // Jumping somewhere twice is a loop
+                    if !self.jumps.insert(offset) {
+                        return Some(());
+                    }
+                    self.position = offset;
+                }
+                0x95 => {
+                    let offset = self.u24()?;
+                    if self.calls.len() == MAX_CALL_DEPTH {
+                        return Some(());
+                    }
+                    self.calls.push(self.position);
+                    self.position = offset;
+                }
+                0xA0 => prefix = Prefix::Random,
+                0xA1 => prefix = Prefix::Variable,
+                0xA2 => {}
+                0xB0..=0xBD => {
+                    self.u8()?;
+                    self.last_argument(this_prefix, |t| t.u16().map(|v| v as u32))?;
+                }
+                0xD0..=0xD3 => {
+                    let value = self.last_argument(this_prefix, |t| t.u8().map(|v| v as u32))?;
+                    let stage = [Stage::Attack, Stage::Decay, Stage::Sustain, Stage::Release]
+                        [(command - 0xD0) as usize];
+                    waiting.push(overrides.len());
+                    overrides.push(Override {
+                        track: self.number,
+                        tick: self.tick,
+                        stage,
+                        value: value.map(|v| v as u8),
+                        program: self.program,
+                        key: None,
+                    });
+                }
+                0xC7 => {
+                    if let Some(mono) =
+                        self.last_argument(this_prefix, |t| t.u8().map(|v| v as u32))?
+                    {
+                        self.note_wait = mono != 0;
+                    }
+                }
+                0xC0..=0xD6 => {
+                    self.last_argument(this_prefix, |t| t.u8().map(|v| v as u32))?;
+                }
+                0xE0 | 0xE1 | 0xE3 => {
+                    self.last_argument(this_prefix, |t| t.u16().map(|v| v as u32))?;
+                }
+                0xFC => {}
+                0xFD => match self.calls.pop() {
+                    Some(position) => self.position = position,
+                    None => return Some(()),
+                },
+                0xFE => {
+                    self.u16()?;
+                }
+                _ => return Some(()),
+            }
+        }
+        Some(())
+    }
+}
+
+/// Every envelope override in the sequence, by track and then by tick
+pub fn overrides(sseq: &[u8]) -> Option<Vec<Override>> {
+    if sseq.get(..4)? != b"SSEQ" {
+        return None;
+    }
+    let data = sseq.get(sseq.u32_at(DATA_OFFSET)? as usize..)?;
+    let mut overrides = Vec::new();
+    let mut open = vec![(0, 0, 0)];
+    let mut started = HashSet::new();
+    while let Some((number, position, tick)) = open.pop() {
+        if !started.insert(number) {
+            continue;
+        }
+        let mut track = Track {
+            data,
+            number,
+            position,
+            tick,
+            program: 0,
+            note_wait: true,
+            calls: Vec::new(),
+            jumps: HashSet::new(),
+        };
+        // A cut off track still has whatever was found before the end
+        track.run(&mut overrides, &mut open);
+    }
+    overrides.sort_by_key(|entry| (entry.track, entry.tick));
+    Some(overrides)
+}
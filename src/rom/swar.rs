@@ -0,0 +1,91 @@
+//! SWAV samples in SWAR wave archives
+//!
+//! Each wave has a 12-byte header with its encoding, rate and loop, lengths being counted in
+//! 32-bit words, followed by 8-bit or 16-bit PCM or IMA-ADPCM. ADPCM waves start with a word
+//! holding the first sample and step index, which the loop start counts too.
+
+use super::{Read, Wave};
+
+const PCM8: u8 = 0;
+const PCM16: u8 = 1;
+const ADPCM: u8 = 2;
+
+const ADPCM_INDEX: [i32; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+const ADPCM_STEPS: [i32; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+fn adpcm(data: &[u8]) -> Option<Vec<i16>> {
+    let mut sample = data.u16_at(0)? as i16 as i32;
+    let mut index = (data.u16_at(2)? as i32).clamp(0, 88);
+    let mut samples = Vec::with_capacity((data.len() - 4) * 2);
+    for &byte in &data[4..] {
+        for nibble in [byte & 0x0F, byte >> 4] {
+            let step = ADPCM_STEPS[index as usize];
+            let mut difference = step >> 3;
+            if nibble & 1 != 0 {
+                difference += step >> 2;
+            }
+            if nibble & 2 != 0 {
+                difference += step >> 1;
+            }
+            if nibble & 4 != 0 {
+                difference += step;
+            }
+            sample = if nibble & 8 != 0 {
+                (sample - difference).max(-0x7FFF)
+            } else {
+                (sample + difference).min(0x7FFF)
+            };
+            index = (index + ADPCM_INDEX[(nibble & 7) as usize]).clamp(0, 88);
+            samples.push(sample as i16);
+        }
+    }
+    Some(samples)
+}
+
+/// Decodes the SWAV at `at`, None if it's cut off or in an unknown encoding
+pub fn read_wave(data: &[u8], at: usize) -> Option<Wave> {
+    let encoding = data.u8_at(at)?;
+    let looped = data.u8_at(at + 1)? != 0;
+    let rate = data.u16_at(at + 2)? as u32;
+    let loop_words = data.u16_at(at + 6)? as usize;
+    let words = loop_words + data.u32_at(at + 8)? as usize;
+    let bytes = data.get(at + 12..at + 12 + words * 4)?;
+    let (samples, loop_start) = match encoding {
+        PCM8 => (
+            bytes.iter().map(|&b| (b as i8 as i16) << 8).collect(),
+            loop_words * 4,
+        ),
+        PCM16 => (
+            bytes
+                .chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+                .collect(),
+            loop_words * 2,
+        ),
+        ADPCM if words > 0 => (adpcm(bytes)?, loop_words.saturating_sub(1) * 8),
+        _ => return None,
+    };
+    Some(Wave {
+        rate,
+        data: samples,
+        loop_start: looped.then_some(loop_start as u32),
+    })
+}
+
+/// A wave from a SWAR by its number
+pub fn wave(swar: &[u8], index: u16) -> Option<Wave> {
+    if swar.get(..4)? != b"SWAR" {
+        return None;
+    }
+    if index as u32 >= swar.u32_at(0x38)? {
+        return None;
+    }
+    let at = swar.u32_at(0x3C + index as usize * 4)? as usize;
+    read_wave(swar, at)
+}